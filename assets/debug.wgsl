@@ -11,6 +11,7 @@ struct Circle {
     radius: f32;
 };
 struct Circles {
+    count: u32;
     circles: array<Circle>;
 };
 [[group(1), binding(0)]]
@@ -22,6 +23,7 @@ struct Line {
     end: vec2<f32>;
 };
 struct Lines {
+    count: u32;
     lines: array<Line>;
 };
 [[group(1), binding(1)]]
@@ -77,14 +79,14 @@ fn mix_with_alpha(a: vec4<f32>, b: vec4<f32>) -> vec4<f32> {
 fn fragment(in: VertexOutput) -> [[location(0)]] vec4<f32> {
     var res = vec4<f32>(0.0, 0.0, 0.0, 0.0);
 
-    let num_circles: u32 = arrayLength(&circles.circles);
+    let num_circles: u32 = min(circles.count, arrayLength(&circles.circles));
     for (var i: u32 = 0u; i < num_circles; i = i + 1u) {
         let c = circles.circles[i];
         let alpha = circlearc(in.world_position.xy, c.center.xy, c.radius);
         res = mix_with_alpha(res, vec4<f32>(c.color.rgb, alpha));
     }
 
-    let num_lines: u32 = arrayLength(&lines.lines);
+    let num_lines: u32 = min(lines.count, arrayLength(&lines.lines));
     for (var i: u32 = 0u; i < num_lines; i = i + 1u) {
         let l = lines.lines[i];
         let alpha = line(in.world_position.xy, l.start, l.end);
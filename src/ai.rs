@@ -0,0 +1,465 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::Inspectable;
+use serde::Deserialize;
+
+use crate::debug::DebugDraw;
+use crate::enemy::Enemy;
+use crate::navigation::{find_path, line_of_sight};
+use crate::player::{nearest_player, Player};
+use crate::raycast::tile_at;
+use crate::steering::{Steered, Steering, Weighted};
+use crate::tilemap::TileMap;
+
+const STATE_MACHINES: &str = include_str!("../assets/ai.ron");
+
+/// How close an agent has to get to a waypoint before moving on to the next one.
+const WAYPOINT_RADIUS: f32 = 0.3;
+/// Seconds before searching again for a path to a goal that couldn't be reached.
+const PATH_RETRY_DELAY: f32 = 1.0;
+
+pub struct AiPlugin;
+
+impl Plugin for AiPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(StateMachines::from_ron(STATE_MACHINES))
+            .add_system(think.label(Thought).before(Steered))
+            .add_system(navigate.after(Thought).before(Steered));
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct Thought;
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AiState {
+    #[default]
+    Patrol,
+    Alert,
+    Chase,
+    Attack,
+    Retreat,
+}
+
+/// Where an agent heads while in a state.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Goal {
+    /// Where the agent was spawned.
+    Home,
+    /// The target itself while it's in sight, otherwise where it was last seen.
+    Target,
+    LastSeen,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub enum Condition {
+    SeesTarget,
+    LostTarget,
+    /// The target is in sight and closer than this.
+    Within(f32),
+    /// The target is further away than this, whether it is in sight or not.
+    Beyond(f32),
+    /// Health as a fraction of the maximum.
+    HealthBelow(f32),
+    /// Seconds spent in the current state.
+    After(f32),
+    All(Vec<Condition>),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StateDef {
+    pub goal: Goal,
+    pub steering: Vec<Weighted>,
+    /// Whether the agent may use its weapon in this state.
+    #[serde(default)]
+    pub attack: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Transition {
+    /// The states this transition leaves from. Empty means any state.
+    #[serde(default)]
+    pub from: Vec<AiState>,
+    pub to: AiState,
+    pub when: Condition,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StateMachine {
+    pub initial: AiState,
+    /// How far the agent can see the target from.
+    pub sight: f32,
+    pub states: HashMap<AiState, StateDef>,
+    /// Checked in order; the first one that applies is taken.
+    pub transitions: Vec<Transition>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct StateMachines {
+    pub machines: HashMap<String, StateMachine>,
+}
+
+impl StateMachines {
+    pub fn from_ron(source: &str) -> Self {
+        ron::from_str(source).expect("invalid AI state machines")
+    }
+}
+
+/// What an agent knows about the world when deciding whether to change state.
+struct Senses {
+    sees_target: bool,
+    distance: f32,
+    health: f32,
+    time_in_state: f32,
+}
+
+impl Condition {
+    fn holds(&self, senses: &Senses) -> bool {
+        match self {
+            Condition::SeesTarget => senses.sees_target,
+            Condition::LostTarget => !senses.sees_target,
+            Condition::Within(distance) => senses.sees_target && senses.distance < *distance,
+            Condition::Beyond(distance) => senses.distance > *distance,
+            Condition::HealthBelow(fraction) => senses.health < *fraction,
+            Condition::After(seconds) => senses.time_in_state >= *seconds,
+            Condition::All(conditions) => conditions.iter().all(|c| c.holds(senses)),
+        }
+    }
+}
+
+impl StateMachine {
+    fn next_state(&self, current: AiState, senses: &Senses) -> Option<AiState> {
+        self.transitions
+            .iter()
+            .filter(|t| t.to != current)
+            .filter(|t| t.from.is_empty() || t.from.contains(&current))
+            .find(|t| t.when.holds(senses))
+            .map(|t| t.to)
+    }
+}
+
+/// Runs one of the [`StateMachines`] by name, driving the entity's [`Steering`].
+#[derive(Component, Inspectable)]
+pub struct Ai {
+    pub machine: String,
+    pub state: AiState,
+    pub time_in_state: f32,
+    pub sees_target: bool,
+    pub last_seen: Option<Vec2>,
+    pub home: Vec2,
+    /// Whether the current state allows attacking.
+    pub attacking: bool,
+    pub path: Vec<IVec2>,
+    #[inspectable(ignore)]
+    path_goal: Option<IVec2>,
+    /// Seconds until a failed search for a path to `path_goal` may be tried again.
+    #[inspectable(ignore)]
+    path_retry: f32,
+    #[inspectable(ignore)]
+    entered: bool,
+}
+
+impl Ai {
+    pub fn new(machine: &str, home: Vec2) -> Self {
+        Ai {
+            machine: machine.to_string(),
+            state: AiState::default(),
+            time_in_state: 0.0,
+            sees_target: false,
+            last_seen: None,
+            home,
+            attacking: false,
+            path: Vec::new(),
+            path_goal: None,
+            path_retry: 0.0,
+            entered: false,
+        }
+    }
+}
+
+fn think(
+    mut agents: Query<(&mut Ai, &mut Steering, &Transform, Option<&Enemy>)>,
+    players: Query<&Transform, With<Player>>,
+    machines: Res<StateMachines>,
+    tilemap: Res<TileMap>,
+    time: Res<Time>,
+) {
+    for (mut ai, mut steering, transform, enemy) in agents.iter_mut() {
+        let machine = match machines.machines.get(&ai.machine) {
+            Some(machine) => machine,
+            None => continue,
+        };
+        if !ai.entered {
+            ai.state = machine.initial;
+        }
+
+        let position = transform.translation.xy();
+        let target = nearest_player(players.iter().map(|tf| tf.translation.xy()), position);
+        let distance = target.map_or(f32::INFINITY, |target| position.distance(target));
+        ai.sees_target = target.is_some_and(|target| {
+            distance <= machine.sight && line_of_sight(&tilemap, position, target)
+        });
+        if ai.sees_target {
+            ai.last_seen = target;
+        }
+        ai.time_in_state += time.delta_seconds();
+
+        let senses = Senses {
+            sees_target: ai.sees_target,
+            distance,
+            health: enemy.map_or(1.0, |enemy| enemy.hit_points / enemy.kind.hit_points()),
+            time_in_state: ai.time_in_state,
+        };
+        let next = machine.next_state(ai.state, &senses);
+        if next.is_none() && ai.entered {
+            continue;
+        }
+
+        ai.state = next.unwrap_or(ai.state);
+        ai.time_in_state = 0.0;
+        ai.entered = true;
+        ai.path.clear();
+        ai.path_goal = None;
+        if let Some(def) = machine.states.get(&ai.state) {
+            steering.behaviours = def.steering.clone();
+            ai.attacking = def.attack;
+        }
+    }
+}
+
+fn navigate(
+    mut agents: Query<(&mut Ai, &mut Steering, &Transform)>,
+    players: Query<&Transform, With<Player>>,
+    machines: Res<StateMachines>,
+    tilemap: Res<TileMap>,
+    time: Res<Time>,
+    mut draw: DebugDraw,
+) {
+    for (mut ai, mut steering, transform) in agents.iter_mut() {
+        ai.path_retry -= time.delta_seconds();
+        let position = transform.translation.xy();
+        let target = nearest_player(players.iter().map(|tf| tf.translation.xy()), position);
+        let goal = machines
+            .machines
+            .get(&ai.machine)
+            .and_then(|machine| machine.states.get(&ai.state))
+            .map(|def| def.goal);
+        let destination = match goal {
+            Some(Goal::Home) => Some(ai.home),
+            Some(Goal::Target) if ai.sees_target => target,
+            Some(Goal::Target | Goal::LastSeen) => ai.last_seen,
+            None => None,
+        };
+
+        let destination = match destination {
+            Some(destination) => destination,
+            None => {
+                steering.target = None;
+                continue;
+            }
+        };
+        if line_of_sight(&tilemap, position, destination) {
+            ai.path.clear();
+            ai.path_goal = None;
+            steering.target = Some(destination);
+            continue;
+        }
+
+        // Out of sight, so find a way around the walls in between.
+        // Unreachable goals are only searched for again once they move or a while has
+        // passed, rather than running a full search every frame.
+        let goal_tile = tile_at(destination);
+        if ai.path_goal != Some(goal_tile) || (ai.path.is_empty() && ai.path_retry <= 0.0) {
+            match find_path(&tilemap, tile_at(position), goal_tile) {
+                Some(path) => ai.path = path,
+                None => {
+                    ai.path.clear();
+                    ai.path_retry = PATH_RETRY_DELAY;
+                }
+            }
+            ai.path_goal = Some(goal_tile);
+        }
+        while ai.path.len() > 1 && ai.path[0].as_vec2().distance(position) < WAYPOINT_RADIUS {
+            ai.path.remove(0);
+        }
+        steering.target = Some(ai.path.first().map_or(destination, |tile| tile.as_vec2()));
+        let waypoints = ai.path.iter().map(|tile| tile.as_vec2());
+        draw.polyline(std::iter::once(position).chain(waypoints), Color::ORANGE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::DebugShapes;
+    use crate::enemy::EnemyKind;
+    use crate::steering::Behaviour;
+    use crate::tilemap::TileKind;
+    use bevy::ecs::schedule::IntoSystemDescriptor;
+
+    fn app<Params>(system: impl IntoSystemDescriptor<Params>, tilemap: TileMap) -> App {
+        let mut app = App::new();
+        app.insert_resource(StateMachines::from_ron(STATE_MACHINES))
+            .insert_resource(tilemap)
+            .init_resource::<Time>()
+            .init_resource::<DebugShapes>()
+            .add_system(system);
+        app
+    }
+
+    fn spawn_agent(app: &mut App, machine: &str, position: Vec2) -> Entity {
+        app.world
+            .spawn()
+            .insert(Ai::new(machine, position))
+            .insert(Steering::new(2.0, 4.0, 0.3))
+            .insert(Transform::from_translation(position.extend(0.0)))
+            .id()
+    }
+
+    fn spawn_player(app: &mut App, position: Vec2) -> Entity {
+        app.world
+            .spawn()
+            .insert(Player::new(0))
+            .insert(Transform::from_translation(position.extend(0.0)))
+            .id()
+    }
+
+    fn state(app: &App, agent: Entity) -> AiState {
+        app.world.get::<Ai>(agent).unwrap().state
+    }
+
+    #[test]
+    fn chaser_chases_what_it_sees_and_gives_up_later() {
+        let mut app = app(think, TileMap::default());
+        let agent = spawn_agent(&mut app, "chaser", Vec2::ZERO);
+        let player = spawn_player(&mut app, Vec2::new(20.0, 0.0));
+
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Patrol);
+        let steering = app.world.get::<Steering>(agent).unwrap();
+        assert!(!steering.behaviours.is_empty());
+
+        app.world.get_mut::<Transform>(player).unwrap().translation = Vec3::new(3.0, 0.0, 0.0);
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Chase);
+        let steering = app.world.get::<Steering>(agent).unwrap();
+        assert_eq!(steering.behaviours[0].behaviour, Behaviour::Seek);
+
+        app.world.get_mut::<Transform>(player).unwrap().translation = Vec3::new(20.0, 0.0, 0.0);
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Alert);
+        let ai = app.world.get::<Ai>(agent).unwrap();
+        assert_eq!(ai.last_seen, Some(Vec2::new(3.0, 0.0)));
+
+        // Nothing changes until the alert has run its course.
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Alert);
+        app.world.get_mut::<Ai>(agent).unwrap().time_in_state = 4.0;
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Patrol);
+    }
+
+    #[test]
+    fn wounded_shooter_retreats_until_out_of_reach() {
+        let mut app = app(think, TileMap::default());
+        let agent = spawn_agent(&mut app, "shooter", Vec2::ZERO);
+        app.world.entity_mut(agent).insert(Enemy {
+            kind: EnemyKind::Shooter,
+            hit_points: EnemyKind::Shooter.hit_points(),
+        });
+        let player = spawn_player(&mut app, Vec2::new(2.0, 0.0));
+
+        app.update();
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Attack);
+        assert!(app.world.get::<Ai>(agent).unwrap().attacking);
+
+        app.world.get_mut::<Enemy>(agent).unwrap().hit_points = 0.1;
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Retreat);
+
+        app.world.get_mut::<Transform>(player).unwrap().translation = Vec3::new(6.5, 0.0, 0.0);
+        app.update();
+        assert_eq!(state(&app, agent), AiState::Attack);
+    }
+
+    /// A floor from (-1, -2) to (6, 2) with a wall across the middle that has to be
+    /// walked around.
+    fn walled_floor() -> TileMap {
+        let mut tilemap = TileMap::default();
+        for x in -1..=6 {
+            for y in -2..=2i32 {
+                let wall = x == 2 && y.abs() <= 1;
+                let kind = if wall {
+                    TileKind::Wall
+                } else {
+                    TileKind::Stone
+                };
+                tilemap.set_tile(x, y, kind);
+            }
+        }
+        tilemap
+    }
+
+    fn alert_agent(app: &mut App, last_seen: Vec2) -> Entity {
+        let agent = spawn_agent(app, "chaser", Vec2::ZERO);
+        let mut ai = app.world.get_mut::<Ai>(agent).unwrap();
+        ai.state = AiState::Alert;
+        ai.entered = true;
+        ai.last_seen = Some(last_seen);
+        agent
+    }
+
+    #[test]
+    fn navigate_paths_around_walls() {
+        let mut app = app(navigate, walled_floor());
+        let agent = alert_agent(&mut app, Vec2::new(4.0, 0.0));
+
+        app.update();
+        let ai = app.world.get::<Ai>(agent).unwrap();
+        assert_eq!(ai.path.last(), Some(&IVec2::new(4, 0)));
+        assert!(ai.path.iter().all(|tile| tile.x != 2 || tile.y.abs() > 1));
+        let steering = app.world.get::<Steering>(agent).unwrap();
+        assert_eq!(steering.target, Some(ai.path[0].as_vec2()));
+    }
+
+    #[test]
+    fn navigate_waits_before_retrying_unreachable_goal() {
+        let mut tilemap = walled_floor();
+        tilemap.set_tile(4, 0, TileKind::Wall);
+        let mut app = app(navigate, tilemap);
+        let agent = alert_agent(&mut app, Vec2::new(4.0, 0.0));
+
+        app.update();
+        assert!(app.world.get::<Ai>(agent).unwrap().path.is_empty());
+
+        // The goal becomes reachable, but the failure is remembered for a while.
+        app.world
+            .resource_mut::<TileMap>()
+            .set_tile(4, 0, TileKind::Stone);
+        app.update();
+        assert!(app.world.get::<Ai>(agent).unwrap().path.is_empty());
+
+        app.world.get_mut::<Ai>(agent).unwrap().path_retry = 0.0;
+        app.update();
+        let ai = app.world.get::<Ai>(agent).unwrap();
+        assert_eq!(ai.path.last(), Some(&IVec2::new(4, 0)));
+    }
+
+    #[test]
+    fn navigate_retries_at_once_when_goal_moves() {
+        let mut tilemap = walled_floor();
+        tilemap.set_tile(4, 0, TileKind::Wall);
+        let mut app = app(navigate, tilemap);
+        let agent = alert_agent(&mut app, Vec2::new(4.0, 0.0));
+
+        app.update();
+        assert!(app.world.get::<Ai>(agent).unwrap().path.is_empty());
+
+        app.world.get_mut::<Ai>(agent).unwrap().last_seen = Some(Vec2::new(4.0, 1.0));
+        app.update();
+        let ai = app.world.get::<Ai>(agent).unwrap();
+        assert_eq!(ai.path.last(), Some(&IVec2::new(4, 1)));
+    }
+}
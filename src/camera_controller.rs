@@ -1,61 +1,520 @@
+use bevy::input::mouse::{MouseMotion, MouseWheel};
 use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
-use bevy::render::camera::ScalingMode;
+use bevy_inspector_egui::Inspectable;
 
-use crate::player::{Player, PlayerMoved};
+use crate::camera_effects::CameraEffectsApplied;
+use crate::player::{Controls, PlayerInput, PlayerMoved};
+use crate::split_screen::{PlayerView, ViewsLaidOut};
+use crate::tilemap::TileMap;
 
+/// Half the height of the view in world units, before any zooming.
 pub const CAMERA_SIZE: f32 = 4.0;
 
+/// How much one notch of the mouse wheel zooms by.
+const ZOOM_STEP: f32 = 0.9;
+/// How much holding a zoom key zooms by per second.
+const ZOOM_KEY_RATE: f32 = 2.0;
+
 pub struct CameraControllerPlugin;
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
 pub struct CameraMoved;
 
+/// Keeps an entity centred on a camera, turning with it. Despawned along with the camera.
 #[derive(Component)]
-pub struct CameraFollower;
+pub struct CameraFollower {
+    pub camera: Entity,
+}
+
+#[derive(Inspectable, Clone, Debug)]
+pub struct CameraSettings {
+    /// Roughly how many seconds the camera takes to catch up with its goal.
+    pub smooth_time: f32,
+    /// Half the size of the box around the camera the player can move in without
+    /// dragging the camera along.
+    pub dead_zone: Vec2,
+    /// How far the view leads towards where its player aims, as a fraction of the aim's
+    /// distance from the centre of the view.
+    pub look_ahead: f32,
+    pub max_look_ahead: f32,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            smooth_time: 0.2,
+            dead_zone: Vec2::new(0.5, 0.3),
+            look_ahead: 0.3,
+            max_look_ahead: 1.5,
+        }
+    }
+}
+
+#[derive(Inspectable, Clone, Debug)]
+pub struct CameraZoom {
+    /// Half the height of the view in world units.
+    pub size: f32,
+    /// The size that `size` is easing towards.
+    pub target: f32,
+    pub min: f32,
+    pub max: f32,
+    /// How quickly `size` catches up with `target`, per second.
+    pub sharpness: f32,
+}
+
+impl Default for CameraZoom {
+    fn default() -> Self {
+        CameraZoom {
+            size: CAMERA_SIZE,
+            target: CAMERA_SIZE,
+            min: 2.0,
+            max: 8.0,
+            sharpness: 10.0,
+        }
+    }
+}
+
+/// Detaches the camera from the player so it can be flown around with the arrow keys
+/// or by dragging with the middle mouse button. Toggled with F2.
+#[derive(Inspectable, Clone, Debug)]
+pub struct FreeCamera {
+    pub enabled: bool,
+    /// Whether the player ignores input while the camera is detached.
+    pub freeze_player: bool,
+    /// In world units per second.
+    pub speed: f32,
+}
+
+impl Default for FreeCamera {
+    fn default() -> Self {
+        FreeCamera {
+            enabled: false,
+            freeze_player: true,
+            speed: 8.0,
+        }
+    }
+}
+
+/// The area the camera may show, in world units. `None` until there's a map.
+#[derive(Default)]
+pub struct CameraBounds(pub Option<(Vec2, Vec2)>);
+
+/// Where the camera is heading and how fast it is getting there.
+#[derive(Component, Default)]
+pub struct CameraRig {
+    position: Vec2,
+    goal: Vec2,
+    velocity: Vec2,
+}
+
+impl CameraRig {
+    /// A rig settled at `position`.
+    pub fn at(position: Vec2) -> Self {
+        CameraRig {
+            position,
+            goal: position,
+            velocity: Vec2::ZERO,
+        }
+    }
+
+    /// Where the camera is, before any shake is applied to its transform.
+    pub fn position(&self) -> Vec2 {
+        self.position
+    }
+}
 
 impl Plugin for CameraControllerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(spawn_camera)
+        app.init_resource::<CameraSettings>()
+            .init_resource::<CameraZoom>()
+            .init_resource::<CameraBounds>()
+            .init_resource::<FreeCamera>()
+            .add_system(toggle_free_camera.before(CameraMoved))
+            .add_system(fly_camera.label(CameraMoved).after(PlayerMoved))
+            .add_system(update_camera_bounds.before(CameraMoved))
+            .add_system(zoom_camera.before(CameraMoved))
+            .add_system(fit_camera_to_view.after(ViewsLaidOut).before(CameraMoved))
             .add_system(follow_player.label(CameraMoved).after(PlayerMoved))
-            .add_system(follow_camera.after(CameraMoved));
+            .add_system(follow_camera.after(CameraEffectsApplied));
+    }
+}
+
+/// Sizes the projection to show `size` world units above and below the centre.
+pub fn fit_projection(projection: &mut OrthographicProjection, size: f32, aspect_ratio: f32) {
+    projection.top = size;
+    projection.bottom = -size;
+    projection.right = size * aspect_ratio;
+    projection.left = -size * aspect_ratio;
+}
+
+/// Keeps the aspect ratio of each camera in line with its slice of the window.
+fn fit_camera_to_view(
+    mut views: Query<(&PlayerView, &mut OrthographicProjection), Changed<PlayerView>>,
+    zoom: Res<CameraZoom>,
+) {
+    for (view, mut projection) in views.iter_mut() {
+        if let Some(aspect_ratio) = view.viewport.aspect_ratio() {
+            fit_projection(&mut projection, zoom.size, aspect_ratio);
+        }
+    }
+}
+
+/// Ctrl + mouse wheel or +/- zoom in and out. The plain wheel is for switching weapons.
+fn zoom_camera(
+    mut zoom: ResMut<CameraZoom>,
+    mut views: Query<(&PlayerView, &mut OrthographicProjection)>,
+    mut wheel: EventReader<MouseWheel>,
+    keyboard: Res<Input<KeyCode>>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_seconds();
+    let scroll: f32 = wheel.iter().map(|ev| ev.y.signum()).sum();
+    let mut target = zoom.target;
+    if keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        target *= ZOOM_STEP.powf(scroll);
+    }
+    if keyboard.any_pressed([KeyCode::Equals, KeyCode::NumpadAdd]) {
+        target /= ZOOM_KEY_RATE.powf(dt);
+    }
+    if keyboard.any_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        target *= ZOOM_KEY_RATE.powf(dt);
+    }
+    zoom.target = target.clamp(zoom.min, zoom.max);
+
+    let blend = 1.0 - (-zoom.sharpness * dt).exp();
+    let size = zoom.size + (zoom.target - zoom.size) * blend;
+    if (size - zoom.size).abs() > f32::EPSILON {
+        zoom.size = size;
+    }
+
+    for (view, mut projection) in views.iter_mut() {
+        if let Some(aspect_ratio) = view.viewport.aspect_ratio() {
+            if projection.top != zoom.size {
+                fit_projection(&mut projection, zoom.size, aspect_ratio);
+            }
+        }
+    }
+}
+
+fn update_camera_bounds(mut bounds: ResMut<CameraBounds>, tilemap: Res<TileMap>) {
+    if tilemap.is_changed() {
+        // Tiles are unit squares centred on their coordinates.
+        bounds.0 = tilemap
+            .extents()
+            .map(|(min, max)| (min.as_vec2() - 0.5, max.as_vec2() + 0.5));
     }
 }
 
-fn spawn_camera(mut commands: Commands, mut windows: ResMut<Windows>) {
-    let window = windows.get_primary_mut().unwrap();
-    let aspect_ratio = window.width() / window.height();
+/// Moves `center` so that a view of `half_size` around it stays within `bounds`,
+/// centring on the bounds along any axis where the view is bigger than them.
+pub fn clamp_view(center: Vec2, half_size: Vec2, (min, max): (Vec2, Vec2)) -> Vec2 {
+    let clamp_axis = |center: f32, half: f32, min: f32, max: f32| {
+        if max - min <= 2.0 * half {
+            (min + max) / 2.0
+        } else {
+            center.clamp(min + half, max - half)
+        }
+    };
+    Vec2::new(
+        clamp_axis(center.x, half_size.x, min.x, max.x),
+        clamp_axis(center.y, half_size.y, min.y, max.y),
+    )
+}
+
+/// Critically damped spring towards `target`, like Unity's `SmoothDamp`: fast to
+/// react, and never overshoots.
+pub fn smooth_damp(
+    current: Vec2,
+    target: Vec2,
+    velocity: &mut Vec2,
+    smooth_time: f32,
+    dt: f32,
+) -> Vec2 {
+    let omega = 2.0 / smooth_time.max(1e-4);
+    let x = omega * dt;
+    let decay = 1.0 / (1.0 + x + 0.48 * x * x + 0.235 * x * x * x);
+    let offset = current - target;
+    let temp = (*velocity + omega * offset) * dt;
+    *velocity = (*velocity - omega * temp) * decay;
+    let mut next = target + (offset + temp) * decay;
 
-    // Create camera
-    let mut camera = OrthographicCameraBundle::new_2d();
-    camera.orthographic_projection.top = CAMERA_SIZE;
-    camera.orthographic_projection.bottom = -CAMERA_SIZE;
-    camera.orthographic_projection.right = CAMERA_SIZE * aspect_ratio;
-    camera.orthographic_projection.left = -CAMERA_SIZE * aspect_ratio;
-    camera.orthographic_projection.scaling_mode = ScalingMode::None;
-    commands
-        .spawn_bundle(camera)
-        .insert(Name::new("Game Camera"));
+    // Don't overshoot when dt is large compared to smooth_time.
+    if (target - current).dot(next - target) > 0.0 {
+        next = target;
+        *velocity = Vec2::ZERO;
+    }
+    next
 }
 
+/// Each view follows its own player, so nobody can walk off screen.
 pub fn follow_player(
-    mut camera: Query<&mut Transform, With<Camera>>,
-    player: Query<&mut Transform, (With<Player>, Without<Camera>)>,
+    mut views: Query<(
+        &PlayerView,
+        &mut Transform,
+        &mut CameraRig,
+        &OrthographicProjection,
+    )>,
+    players: Query<(&Transform, &PlayerInput, &Controls), Without<PlayerView>>,
+    settings: Res<CameraSettings>,
+    bounds: Res<CameraBounds>,
+    free_camera: Res<FreeCamera>,
+    time: Res<Time>,
 ) {
-    let mut camera = camera.single_mut();
-    let player = player.single();
+    for (view, mut camera, mut rig, projection) in views.iter_mut() {
+        let (player, input, &controls) = match players.get(view.player) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        if free_camera.enabled && controls == Controls::KeyboardMouse {
+            // Being flown by fly_camera.
+            continue;
+        }
+
+        // The aim's offset from the centre of the view, rather than from the player,
+        // so that leading the view doesn't in turn push a mouse cursor further out.
+        let aim = input.aim - rig.position;
+        let look = (settings.look_ahead * aim).clamp_length_max(settings.max_look_ahead);
+        let desired = player.translation.xy() + look;
+
+        let offset = desired - rig.goal;
+        let outside = offset.abs() - settings.dead_zone;
+        rig.goal += outside.max(Vec2::ZERO) * offset.signum();
 
-    camera.translation = player.translation.xy().extend(999.9);
+        let half_view = projection.scale * Vec2::new(projection.right, projection.top);
+        if let Some(bounds) = bounds.0 {
+            rig.goal = clamp_view(rig.goal, half_view, bounds);
+        }
+
+        let mut velocity = rig.velocity;
+        let position = smooth_damp(
+            rig.position,
+            rig.goal,
+            &mut velocity,
+            settings.smooth_time,
+            time.delta_seconds(),
+        );
+        rig.velocity = velocity;
+        // The goal is already in bounds, but the view may have grown since by zooming out.
+        let position = match bounds.0 {
+            Some(bounds) => clamp_view(position, half_view, bounds),
+            None => position,
+        };
+        rig.position = position;
+        camera.translation = position.extend(camera.translation.z);
+    }
+}
+
+fn toggle_free_camera(mut free_camera: ResMut<FreeCamera>, keyboard: Res<Input<KeyCode>>) {
+    if keyboard.just_pressed(KeyCode::F2) {
+        free_camera.enabled = !free_camera.enabled;
+    }
+}
+
+/// Flies the keyboard and mouse player's view. Everyone else's keeps following them.
+fn fly_camera(
+    mut views: Query<(
+        &PlayerView,
+        &mut Transform,
+        &mut CameraRig,
+        &OrthographicProjection,
+    )>,
+    players: Query<&Controls>,
+    free_camera: Res<FreeCamera>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
+    mut motion: EventReader<MouseMotion>,
+    time: Res<Time>,
+) {
+    // Drain the events either way so a drag doesn't jump when flying is switched on.
+    let dragged: Vec2 = motion.iter().map(|ev| &ev.delta).sum();
+    if !free_camera.enabled {
+        return;
+    }
+    let (view, mut camera, mut rig, projection) = match views
+        .iter_mut()
+        .find(|(view, ..)| players.get(view.player) == Ok(&Controls::KeyboardMouse))
+    {
+        Some(view) => view,
+        None => return,
+    };
+
+    let mut direction = Vec2::ZERO;
+    if keyboard.pressed(KeyCode::Up) {
+        direction.y += 1.0;
+    }
+    if keyboard.pressed(KeyCode::Down) {
+        direction.y -= 1.0;
+    }
+    if keyboard.pressed(KeyCode::Left) {
+        direction.x -= 1.0;
+    }
+    if keyboard.pressed(KeyCode::Right) {
+        direction.x += 1.0;
+    }
+    let mut position =
+        rig.position + direction.normalize_or_zero() * free_camera.speed * time.delta_seconds();
+
+    if mouse.pressed(MouseButton::Middle) && view.viewport.size.y > 0.0 {
+        let units_per_pixel =
+            projection.scale * (projection.top - projection.bottom) / view.viewport.size.y;
+        // Screen y grows downwards in mouse motion, world y grows upwards.
+        position += units_per_pixel * Vec2::new(-dragged.x, dragged.y);
+    }
+
+    rig.position = position;
+    // Come back to the player smoothly from here once flying is switched off.
+    rig.goal = position;
+    rig.velocity = Vec2::ZERO;
+    camera.translation = position.extend(camera.translation.z);
 }
 
 fn follow_camera(
-    mut followers: Query<&mut Transform, With<CameraFollower>>,
-    camera: Query<&Transform, (With<Camera>, Without<CameraFollower>)>,
+    mut commands: Commands,
+    mut followers: Query<(Entity, &CameraFollower, &mut Transform)>,
+    cameras: Query<&Transform, (With<Camera>, Without<CameraFollower>)>,
 ) {
-    let camera = camera.single();
+    for (entity, follower, mut transform) in followers.iter_mut() {
+        let camera = match cameras.get(follower.camera) {
+            Ok(camera) => camera,
+            Err(_) => {
+                commands.entity(entity).despawn_recursive();
+                continue;
+            }
+        };
+        transform.translation.x = camera.translation.x;
+        transform.translation.y = camera.translation.y;
+        transform.rotation = camera.rotation;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split_screen::lay_out_views;
+    use bevy::asset::AssetPlugin;
+    use bevy::render::camera::RenderTarget;
+    use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+    use bevy::window::{WindowDescriptor, WindowId};
+    use raw_window_handle::{RawWindowHandle, WebHandle};
+
+    #[test]
+    fn each_view_follows_its_own_player() {
+        let mut app = App::new();
+        app.init_resource::<CameraSettings>()
+            .init_resource::<CameraBounds>()
+            .init_resource::<FreeCamera>()
+            .init_resource::<Time>()
+            .add_system(follow_player);
+
+        let dead_zone = CameraSettings::default().dead_zone;
+        let spots = [Vec2::new(10.0, 0.0), Vec2::new(-10.0, 5.0)];
+        let views: Vec<Entity> = spots
+            .iter()
+            .enumerate()
+            .map(|(index, &spot)| {
+                let player = app
+                    .world
+                    .spawn()
+                    .insert(Transform::from_translation(spot.extend(0.0)))
+                    // Aiming at the middle of the view, so it doesn't lead anywhere.
+                    .insert(PlayerInput::default())
+                    .insert(Controls::KeyboardMouse)
+                    .id();
+                let pane = app.world.spawn().id();
+                app.world
+                    .spawn()
+                    .insert(PlayerView::new(player, index, pane))
+                    .insert(Transform::default())
+                    .insert(CameraRig::default())
+                    .insert(OrthographicProjection::default())
+                    .id()
+            })
+            .collect();
+
+        app.update();
+
+        for (view, spot) in views.into_iter().zip(spots) {
+            let goal = app.world.get::<CameraRig>(view).unwrap().goal;
+            let behind = (spot - goal).abs();
+            assert!(
+                behind.cmple(dead_zone + 1e-4).all(),
+                "view heading for {} instead of {}",
+                goal,
+                spot
+            );
+        }
+    }
+
+    #[test]
+    fn resizing_the_window_refits_the_projection() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Image>()
+            .init_resource::<CameraZoom>()
+            .init_resource::<Windows>()
+            .add_system(lay_out_views.label(ViewsLaidOut))
+            .add_system(fit_camera_to_view.after(ViewsLaidOut));
+
+        app.world.resource_mut::<Windows>().add(Window::new(
+            WindowId::primary(),
+            &WindowDescriptor::default(),
+            1280,
+            720,
+            1.0,
+            None,
+            RawWindowHandle::Web(WebHandle::empty()),
+        ));
+        let image = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(Image::new_fill(
+                Extent3d::default(),
+                TextureDimension::D2,
+                &[0; 4],
+                TextureFormat::Bgra8UnormSrgb,
+            ));
+        let player = app.world.spawn().id();
+        let pane = app.world.spawn().insert(Transform::default()).id();
+        let view = app
+            .world
+            .spawn()
+            .insert(PlayerView::new(player, 0, pane))
+            .insert(Camera {
+                target: RenderTarget::Image(image.clone()),
+                ..Default::default()
+            })
+            .insert(OrthographicProjection::default())
+            .id();
+
+        let extents = |app: &App| {
+            let projection = app.world.get::<OrthographicProjection>(view).unwrap();
+            Vec2::new(projection.right, projection.top)
+        };
+        let image_size = |app: &App| {
+            let size = app
+                .world
+                .resource::<Assets<Image>>()
+                .get(&image)
+                .unwrap()
+                .size();
+            Vec2::new(size.x, size.y)
+        };
+
+        app.update();
+        let wide = Vec2::new(CAMERA_SIZE * 1280.0 / 720.0, CAMERA_SIZE);
+        assert!(extents(&app).distance(wide) < 1e-4, "{}", extents(&app));
+        assert_eq!(image_size(&app), Vec2::new(1280.0, 720.0));
 
-    for mut follower in followers.iter_mut() {
-        follower.translation.x = camera.translation.x;
-        follower.translation.y = camera.translation.y;
+        app.world
+            .resource_mut::<Windows>()
+            .get_primary_mut()
+            .unwrap()
+            .update_actual_size_from_backend(600, 800);
+        app.update();
+        let tall = Vec2::new(CAMERA_SIZE * 600.0 / 800.0, CAMERA_SIZE);
+        assert!(extents(&app).distance(tall) < 1e-4, "{}", extents(&app));
+        assert_eq!(image_size(&app), Vec2::new(600.0, 800.0));
     }
 }
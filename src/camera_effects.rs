@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::Inspectable;
+
+use crate::camera_controller::{CameraMoved, CameraRig};
+use crate::split_screen::Compositor;
+
+pub struct CameraEffectsPlugin;
+
+impl Plugin for CameraEffectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ShakeSettings>()
+            .init_resource::<CameraEffects>()
+            .add_event::<CameraShake>()
+            .add_startup_system_to_stage(StartupStage::PostStartup, spawn_flash)
+            .add_system(
+                apply_camera_effects
+                    .label(CameraEffectsApplied)
+                    .after(CameraMoved),
+            );
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct CameraEffectsApplied;
+
+/// Adds trauma to the camera. Shake grows with the square of trauma, so small
+/// knocks barely register while several big ones in a row get violent.
+pub struct CameraShake {
+    pub trauma: f32,
+}
+
+#[derive(Inspectable, Clone, Debug)]
+pub struct ShakeSettings {
+    pub max_offset: f32,
+    /// In radians.
+    pub max_angle: f32,
+    /// Trauma lost per second.
+    pub decay: f32,
+    /// How fast the shake wobbles.
+    pub frequency: f32,
+}
+
+impl Default for ShakeSettings {
+    fn default() -> Self {
+        ShakeSettings {
+            max_offset: 0.3,
+            max_angle: 0.05,
+            decay: 1.5,
+            frequency: 15.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CameraEffectKind {
+    /// Briefly zooms in by this fraction of the view and springs back.
+    ZoomPunch { amount: f32 },
+    /// Tints the whole screen, fading out.
+    Flash { color: Color },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraEffect {
+    pub kind: CameraEffectKind,
+    pub duration: f32,
+    elapsed: f32,
+}
+
+impl CameraEffect {
+    pub fn new(kind: CameraEffectKind, duration: f32) -> Self {
+        CameraEffect {
+            kind,
+            duration,
+            elapsed: 0.0,
+        }
+    }
+
+    /// 1 when the effect starts, falling to 0 when it ends.
+    fn strength(&self) -> f32 {
+        (1.0 - self.elapsed / self.duration).clamp(0.0, 1.0)
+    }
+}
+
+/// Effects currently playing on the camera. Ones of the same kind stack.
+#[derive(Default)]
+pub struct CameraEffects {
+    trauma: f32,
+    effects: Vec<CameraEffect>,
+}
+
+impl CameraEffects {
+    pub fn push(&mut self, effect: CameraEffect) {
+        self.effects.push(effect);
+    }
+}
+
+#[derive(Component)]
+struct Flash;
+
+/// The flash covers the whole window, on top of every player's view.
+fn spawn_flash(mut commands: Commands, compositor: Query<Entity, With<Compositor>>) {
+    let flash = commands
+        .spawn_bundle(SpriteBundle {
+            // In front of the player views, relative to the camera.
+            transform: Transform::from_translation(Vec3::new(0.0, 0.0, -5.0)),
+            sprite: Sprite {
+                color: Color::NONE,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Flash)
+        .insert(Name::new("Camera Flash"))
+        .id();
+    commands.entity(compositor.single()).add_child(flash);
+}
+
+/// Smooth noise in [-1, 1] built from a few incommensurate sines. `seed` picks the channel.
+fn wobble(seed: f32, t: f32) -> f32 {
+    ((t * 1.0 + seed * 12.9).sin() * 0.5
+        + (t * 2.3 + seed * 78.2).sin() * 0.3
+        + (t * 4.7 + seed * 37.7).sin() * 0.2)
+        .clamp(-1.0, 1.0)
+}
+
+/// Layers shake and effects on top of the position [`CameraRig`] settled on. Anything
+/// that needs the steady view, like aiming, should read the rig instead of the transform.
+fn apply_camera_effects(
+    mut effects: ResMut<CameraEffects>,
+    mut shakes: EventReader<CameraShake>,
+    settings: Res<ShakeSettings>,
+    mut cameras: Query<(&CameraRig, &mut Transform, &mut OrthographicProjection)>,
+    mut flash: Query<&mut Sprite, With<Flash>>,
+    windows: Res<Windows>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_seconds();
+    for shake in shakes.iter() {
+        effects.trauma = (effects.trauma + shake.trauma).min(1.0);
+    }
+    effects.trauma = (effects.trauma - settings.decay * dt).max(0.0);
+    for effect in effects.effects.iter_mut() {
+        effect.elapsed += dt;
+    }
+    effects
+        .effects
+        .retain(|effect| effect.elapsed < effect.duration);
+
+    let mut punch = 0.0;
+    let mut tint = Color::NONE;
+    for effect in effects.effects.iter() {
+        match effect.kind {
+            CameraEffectKind::ZoomPunch { amount } => punch += amount * effect.strength(),
+            CameraEffectKind::Flash { color } => {
+                let mut faded = color;
+                faded.set_a(color.a() * effect.strength());
+                if faded.a() > tint.a() {
+                    tint = faded;
+                }
+            }
+        }
+    }
+
+    // Everyone shares the screen, so everyone gets shaken.
+    let shake = effects.trauma * effects.trauma;
+    let t = time.seconds_since_startup() as f32 * settings.frequency;
+    let offset = settings.max_offset * shake * Vec2::new(wobble(1.0, t), wobble(2.0, t));
+    let angle = settings.max_angle * shake * wobble(3.0, t);
+    let scale = 1.0 - punch.min(0.5);
+    for (rig, mut transform, mut projection) in cameras.iter_mut() {
+        transform.translation = (rig.position() + offset).extend(transform.translation.z);
+        transform.rotation = Quat::from_rotation_z(angle);
+        if projection.scale != scale {
+            projection.scale = scale;
+        }
+    }
+
+    if let Ok(mut sprite) = flash.get_single_mut() {
+        let window = windows.get_primary().unwrap();
+        sprite.custom_size = Some(Vec2::new(window.width(), window.height()));
+        sprite.color = tint;
+    }
+}
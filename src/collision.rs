@@ -0,0 +1,309 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bitflags::bitflags;
+
+use crate::raycast::{ray_circle, segment_circle};
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialGrid>()
+            .add_event::<CollisionEvent>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                index_colliders.label(CollidersIndexed),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                detect_collisions
+                    .label(CollisionsDetected)
+                    .after(CollidersIndexed),
+            );
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct CollidersIndexed;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct CollisionsDetected;
+
+bitflags! {
+    pub struct Layers: u32 {
+        const PLAYER = 1 << 0;
+        const ENEMY = 1 << 1;
+        const PROJECTILE = 1 << 2;
+        const TERRAIN = 1 << 3;
+        const PICKUP = 1 << 4;
+    }
+}
+
+/// A circular collider. `layers` is what the entity is, `mask` is what it reports
+/// collisions with.
+#[derive(Component, Clone, Copy)]
+pub struct Collider {
+    pub radius: f32,
+    pub layers: Layers,
+    pub mask: Layers,
+}
+
+/// Marks a collider as a projectile: it is tested along its path and resolves
+/// at most one hit per frame, against whatever it reaches first.
+#[derive(Component, Default)]
+pub struct Swept {
+    pub path: Vec<Vec2>,
+}
+
+pub struct CollisionEvent {
+    /// The entity whose mask matched. For projectiles this is the projectile.
+    pub entity: Entity,
+    pub other: Entity,
+}
+
+#[derive(Clone, Copy)]
+struct GridEntry {
+    entity: Entity,
+    center: Vec2,
+    radius: f32,
+    layers: Layers,
+}
+
+/// A uniform grid over all colliders, rebuilt every frame.
+pub struct SpatialGrid {
+    cell_size: f32,
+    max_radius: f32,
+    entries: Vec<GridEntry>,
+    cells: HashMap<IVec2, Vec<usize>>,
+}
+
+impl Default for SpatialGrid {
+    fn default() -> Self {
+        SpatialGrid::new(1.0)
+    }
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> Self {
+        SpatialGrid {
+            cell_size,
+            max_radius: 0.0,
+            entries: Vec::new(),
+            cells: HashMap::default(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.max_radius = 0.0;
+        self.entries.clear();
+        // Keep the cells used last frame for their allocations, but drop the ones that
+        // stayed empty so the map doesn't grow with every cell anything ever passed through.
+        self.cells.retain(|_, cell| {
+            let used = !cell.is_empty();
+            cell.clear();
+            used
+        });
+    }
+
+    pub fn insert(&mut self, entity: Entity, center: Vec2, radius: f32, layers: Layers) {
+        let index = self.entries.len();
+        self.entries.push(GridEntry {
+            entity,
+            center,
+            radius,
+            layers,
+        });
+        self.max_radius = self.max_radius.max(radius);
+        let cell = self.cell(center);
+        self.cells.entry(cell).or_default().push(index);
+    }
+
+    fn cell(&self, point: Vec2) -> IVec2 {
+        (point / self.cell_size).floor().as_ivec2()
+    }
+
+    /// Entries whose cell lies in the box, grown by the largest radius so that
+    /// colliders overlapping the box from a neighbouring cell are included.
+    fn entries_in(&self, min: Vec2, max: Vec2) -> impl Iterator<Item = &GridEntry> {
+        let min = self.cell(min - Vec2::splat(self.max_radius));
+        let max = self.cell(max + Vec2::splat(self.max_radius));
+        (min.x..=max.x)
+            .flat_map(move |x| (min.y..=max.y).map(move |y| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(|&index| &self.entries[index])
+    }
+
+    /// All entities in `mask` overlapping the circle.
+    pub fn query_circle(
+        &self,
+        center: Vec2,
+        radius: f32,
+        mask: Layers,
+    ) -> impl Iterator<Item = Entity> + '_ {
+        self.entries_in(center - Vec2::splat(radius), center + Vec2::splat(radius))
+            .filter(move |e| e.layers.intersects(mask))
+            .filter(move |e| e.center.distance(center) <= e.radius + radius)
+            .map(|e| e.entity)
+    }
+
+    /// The first entity in `mask` touched by a circle of `radius` moving from
+    /// `start` to `end`, with the distance travelled to reach it.
+    pub fn cast_segment(
+        &self,
+        start: Vec2,
+        end: Vec2,
+        radius: f32,
+        mask: Layers,
+        exclude: impl Fn(Entity) -> bool,
+    ) -> Option<(Entity, f32)> {
+        self.entries_in(
+            start.min(end) - Vec2::splat(radius),
+            start.max(end) + Vec2::splat(radius),
+        )
+        .filter(|e| e.layers.intersects(mask) && !exclude(e.entity))
+        .filter_map(|e| {
+            segment_circle(start, end, e.center, e.radius + radius).map(|d| (e.entity, d))
+        })
+        .min_by(|(a_ent, a), (b_ent, b)| a.total_cmp(b).then(a_ent.cmp(b_ent)))
+    }
+
+    /// Like [`SpatialGrid::cast_segment`] for a ray of the given length.
+    pub fn cast_ray(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        length: f32,
+        mask: Layers,
+    ) -> Option<(Entity, f32)> {
+        let end = origin + length * direction;
+        self.entries_in(origin.min(end), origin.max(end))
+            .filter(|e| e.layers.intersects(mask))
+            .filter_map(|e| {
+                ray_circle(origin, direction, e.center, e.radius)
+                    .filter(|&d| d <= length)
+                    .map(|d| (e.entity, d))
+            })
+            .min_by(|(a_ent, a), (b_ent, b)| a.total_cmp(b).then(a_ent.cmp(b_ent)))
+    }
+}
+
+fn index_colliders(
+    mut grid: ResMut<SpatialGrid>,
+    colliders: Query<(Entity, &Transform, &Collider)>,
+) {
+    grid.clear();
+    for (entity, tf, collider) in colliders.iter() {
+        grid.insert(
+            entity,
+            tf.translation.xy(),
+            collider.radius,
+            collider.layers,
+        );
+    }
+}
+
+fn detect_collisions(
+    grid: Res<SpatialGrid>,
+    colliders: Query<(Entity, &Transform, &Collider, Option<&Swept>)>,
+    mut events: EventWriter<CollisionEvent>,
+) {
+    for (entity, tf, collider, swept) in colliders.iter() {
+        if collider.mask.is_empty() {
+            continue;
+        }
+
+        if let Some(swept) = swept {
+            // Segments are in travel order, so the first one with a hit holds the nearest.
+            let hit = swept.path.windows(2).find_map(|segment| {
+                let (start, end) = (segment[0], segment[1]);
+                grid.cast_segment(start, end, collider.radius, collider.mask, |other| {
+                    other == entity
+                })
+            });
+            if let Some((other, _)) = hit {
+                events.send(CollisionEvent { entity, other });
+            }
+            continue;
+        }
+
+        let center = tf.translation.xy();
+        for other in grid.query_circle(center, collider.radius, collider.mask) {
+            if other != entity {
+                events.send(CollisionEvent { entity, other });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn clear_drops_cells_left_behind() {
+        let mut grid = SpatialGrid::new(1.0);
+        let entity = Entity::from_raw(0);
+        for step in 0..100 {
+            grid.clear();
+            grid.insert(entity, Vec2::new(step as f32, 0.0), 0.5, Layers::TERRAIN);
+        }
+        // The cell from this frame, and the one from last frame that just emptied.
+        assert!(grid.cells.len() <= 2);
+    }
+
+    #[test]
+    fn query_circle_finds_overlaps_across_cells() {
+        let mut grid = SpatialGrid::new(1.0);
+        let near = Entity::from_raw(0);
+        let far = Entity::from_raw(1);
+        grid.insert(near, Vec2::new(1.9, 0.0), 0.5, Layers::TERRAIN);
+        grid.insert(far, Vec2::new(5.0, 0.0), 0.5, Layers::TERRAIN);
+        let found: Vec<Entity> = grid.query_circle(Vec2::ZERO, 1.5, Layers::all()).collect();
+        assert_eq!(found, vec![near]);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture` to see the timings.
+    #[test]
+    #[ignore]
+    fn bench_ten_thousand_rocks() {
+        const ROCKS: u32 = 10_000;
+        const FRAMES: u32 = 60;
+        let positions: Vec<Vec2> = (0..ROCKS)
+            .map(|i| {
+                // Spread the rocks over a 100x100 area without pulling in an RNG.
+                let t = i as f32;
+                Vec2::new((t * 0.618_034).fract(), (t * 0.754_878).fract()) * 100.0
+            })
+            .collect();
+        let mut grid = SpatialGrid::default();
+
+        let start = Instant::now();
+        let mut overlaps = 0;
+        for frame in 0..FRAMES {
+            grid.clear();
+            let drift = Vec2::splat(frame as f32 * 0.05);
+            for (i, &position) in positions.iter().enumerate() {
+                grid.insert(
+                    Entity::from_raw(i as u32),
+                    position + drift,
+                    0.3,
+                    Layers::TERRAIN,
+                );
+            }
+            for &position in positions.iter() {
+                overlaps += grid
+                    .query_circle(position + drift, 0.3, Layers::TERRAIN)
+                    .count();
+            }
+        }
+        let per_frame = start.elapsed() / FRAMES;
+        println!(
+            "{} rocks: {:?} per frame to rebuild and query the grid ({} overlaps)",
+            ROCKS, per_frame, overlaps
+        );
+        assert!(overlaps >= (ROCKS * FRAMES) as usize);
+    }
+}
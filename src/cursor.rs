@@ -1,8 +1,24 @@
+use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
+use bevy::render::camera::CameraProjection;
+use bevy::utils::HashMap;
+use serde::Deserialize;
 
-use crate::camera_controller::{CameraMoved, CAMERA_SIZE};
+use crate::camera_controller::{CameraMoved, CameraRig};
+use crate::collision::{Layers, SpatialGrid};
+use crate::player::{Controls, PlayerInput, PlayerInputRead};
+use crate::raycast::tile_at;
+use crate::split_screen::PlayerView;
 
 const CURSOR_SPRITE: &str = "cursor.png";
+const CURSOR_STYLES: &str = include_str!("../assets/cursors.ron");
+
+/// How close to the cursor something has to be to count as under it.
+const HOVER_RADIUS: f32 = 0.05;
+const HOVER_TARGET_PRIORITY: i32 = 10;
+const HOVER_PICKUP_PRIORITY: i32 = 20;
+/// Above the hover styles, so a spawner being placed isn't hidden by what it's over.
+pub const PLACEMENT_PRIORITY: i32 = 30;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CursorState {
@@ -13,65 +29,247 @@ pub enum CursorState {
 #[derive(Component)]
 pub struct Cursor;
 
+/// Shows where a player without a mouse is aiming.
+#[derive(Component)]
+pub struct Reticle {
+    pub player: Entity,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CursorStyle {
+    #[default]
+    Default,
+    /// Over something that can be shot.
+    Crosshair,
+    /// Over something that can be picked up.
+    Hand,
+    /// Previews the tile an editor brush would paint.
+    Brush,
+    /// Where a brush or placement isn't allowed.
+    Invalid,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CursorStyleDef {
+    pub sprite: String,
+    pub color: (f32, f32, f32, f32),
+    /// In world units.
+    pub size: f32,
+    /// Sit on the hovered tile instead of following the cursor exactly.
+    #[serde(default)]
+    pub snap: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct CursorStyles {
+    pub styles: HashMap<CursorStyle, CursorStyleDef>,
+}
+
+impl CursorStyles {
+    pub fn from_ron(source: &str) -> Self {
+        ron::from_str(source).expect("invalid cursor styles")
+    }
+}
+
+/// The styles asked for this frame. The highest priority one is shown, and the stack
+/// is cleared once it has been applied, so anything that wants a style keeps pushing
+/// it every frame from a system that runs before [`CursorStyled`].
+#[derive(Default)]
+pub struct CursorRequests {
+    requests: Vec<(i32, CursorStyle)>,
+}
+
+impl CursorRequests {
+    pub fn push(&mut self, style: CursorStyle, priority: i32) {
+        self.requests.push((priority, style));
+    }
+
+    /// The highest priority request, with ties going to whichever was pushed first.
+    pub fn top(&self) -> Option<CursorStyle> {
+        self.requests
+            .iter()
+            .rev()
+            .max_by_key(|(priority, _)| *priority)
+            .map(|(_, style)| *style)
+    }
+}
+
 pub struct CursorPlugin;
 
 impl Plugin for CursorPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(MousePos {
-            x: 0.0,
-            y: 0.0,
-            screen_x: 0.0,
-            screen_y: 0.0,
-        })
-        .add_startup_system(spawn_mouse_cursor)
-        .add_system(update_mouse_pos.label(UpdatedMousePos).after(CameraMoved))
-        .add_system(place_mouse_cursor.after(UpdatedMousePos));
+        app.init_resource::<MousePos>()
+            .insert_resource(CursorStyles::from_ron(CURSOR_STYLES))
+            .init_resource::<CursorRequests>()
+            .add_startup_system(spawn_mouse_cursor)
+            .add_system(update_mouse_pos.label(UpdatedMousePos).after(CameraMoved))
+            .add_system(
+                request_hover_styles
+                    .after(UpdatedMousePos)
+                    .before(CursorStyled),
+            )
+            .add_system(
+                place_mouse_cursor
+                    .label(CursorStyled)
+                    .after(UpdatedMousePos),
+            )
+            .add_system(place_reticles.after(PlayerInputRead));
     }
 }
 
+#[derive(Default)]
 pub struct MousePos {
     pub x: f32,
     pub y: f32,
-    #[allow(dead_code)]
-    pub screen_x: f32,
-    #[allow(dead_code)]
-    pub screen_y: f32,
+    /// In window pixels from the bottom left corner. `None` until the cursor first
+    /// enters the window.
+    pub pixel: Option<Vec2>,
+    /// The tile under the cursor.
+    pub tile: IVec2,
+}
+
+/// Converts a position in window pixels, from the bottom left corner, to world space
+/// as seen by a camera with this projection and transform.
+pub fn screen_to_world(
+    pixel: Vec2,
+    window_size: Vec2,
+    projection: &OrthographicProjection,
+    camera: &GlobalTransform,
+) -> Vec2 {
+    let ndc = 2.0 * pixel / window_size - Vec2::ONE;
+    let ndc_to_world = camera.compute_matrix() * projection.get_projection_matrix().inverse();
+    ndc_to_world.project_point3(ndc.extend(0.0)).xy()
+}
+
+/// The inverse of [`screen_to_world`].
+pub fn world_to_screen(
+    position: Vec2,
+    window_size: Vec2,
+    projection: &OrthographicProjection,
+    camera: &GlobalTransform,
+) -> Vec2 {
+    let world_to_ndc = projection.get_projection_matrix() * camera.compute_matrix().inverse();
+    let ndc = world_to_ndc.project_point3(position.extend(0.0)).xy();
+    (ndc + Vec2::ONE) / 2.0 * window_size
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
 pub struct UpdatedMousePos;
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct CursorStyled;
+
 fn update_mouse_pos(
-    windows: Res<Windows>,
     mut mouse_moved_event: EventReader<CursorMoved>,
     mut mouse_pos: ResMut<MousePos>,
-    camera: Query<&Transform, With<Camera>>,
+    views: Query<(&PlayerView, &CameraRig, &OrthographicProjection)>,
+    players: Query<&Controls>,
 ) {
-    for ev in mouse_moved_event.iter() {
-        let window = windows.get_primary().unwrap();
-        let width = window.width();
-        let height = window.height();
-        let (mx, my) = (ev.position.x, ev.position.y);
-        let mx = 2.0 * CAMERA_SIZE * (mx - width / 2.0) / height;
-        let my = 2.0 * CAMERA_SIZE * (my - height / 2.0) / height;
-        mouse_pos.screen_x = mx;
-        mouse_pos.screen_y = my;
+    if let Some(ev) = mouse_moved_event.iter().last() {
+        mouse_pos.pixel = Some(ev.position);
     }
+    let pixel = match mouse_pos.pixel {
+        Some(pixel) => pixel,
+        None => return,
+    };
 
-    let camera_translation = camera.single().translation;
+    // Recomputed every frame, not just when the cursor moves, since the camera keeps
+    // drifting after the player stops and the projection changes while zooming. The
+    // rig's position leaves out screen shake, which shouldn't throw off aiming.
+    // The mouse belongs to the keyboard player, so it maps through their view even
+    // while it's over someone else's.
+    let (view, rig, projection) = match views
+        .iter()
+        .find(|(view, ..)| players.get(view.player) == Ok(&Controls::KeyboardMouse))
+    {
+        Some(view) => view,
+        None => return,
+    };
+    if view.viewport.aspect_ratio().is_none() {
+        return;
+    }
+    let steady = GlobalTransform::from_translation(rig.position().extend(0.0));
+    let local = pixel - view.viewport.min;
+    let world = screen_to_world(local, view.viewport.size, projection, &steady);
 
-    mouse_pos.x = mouse_pos.screen_x + camera_translation.x;
-    mouse_pos.y = mouse_pos.screen_y + camera_translation.y;
+    mouse_pos.x = world.x;
+    mouse_pos.y = world.y;
+    mouse_pos.tile = tile_at(world);
 }
 
-fn place_mouse_cursor(mut cursor: Query<&mut Transform, With<Cursor>>, mouse_pos: Res<MousePos>) {
-    let mut cursor = cursor.single_mut();
-    cursor.translation = Vec3::new(mouse_pos.x, mouse_pos.y, 0.1);
+fn request_hover_styles(
+    mut requests: ResMut<CursorRequests>,
+    mouse_pos: Res<MousePos>,
+    grid: Res<SpatialGrid>,
+) {
+    let position = Vec2::new(mouse_pos.x, mouse_pos.y);
+    let hovering = |layers| {
+        grid.query_circle(position, HOVER_RADIUS, layers)
+            .next()
+            .is_some()
+    };
+    if hovering(Layers::PICKUP) {
+        requests.push(CursorStyle::Hand, HOVER_PICKUP_PRIORITY);
+    }
+    if hovering(Layers::ENEMY | Layers::TERRAIN) {
+        requests.push(CursorStyle::Crosshair, HOVER_TARGET_PRIORITY);
+    }
+}
+
+fn place_mouse_cursor(
+    mut cursor: Query<(&mut Transform, &mut Sprite, &mut Handle<Image>), With<Cursor>>,
+    mut requests: ResMut<CursorRequests>,
+    mut shown: Local<Option<CursorStyle>>,
+    styles: Res<CursorStyles>,
+    mouse_pos: Res<MousePos>,
+    assets: Res<AssetServer>,
+) {
+    let style = requests.top().unwrap_or_default();
+    requests.requests.clear();
+    let def = match styles
+        .styles
+        .get(&style)
+        .or_else(|| styles.styles.get(&CursorStyle::Default))
+    {
+        Some(def) => def,
+        None => return,
+    };
+
+    let (mut cursor, mut sprite, mut texture) = cursor.single_mut();
+    let position = if def.snap {
+        mouse_pos.tile.as_vec2()
+    } else {
+        Vec2::new(mouse_pos.x, mouse_pos.y)
+    };
+    cursor.translation = position.extend(0.1);
+
+    if *shown != Some(style) {
+        *shown = Some(style);
+        let (r, g, b, a) = def.color;
+        sprite.color = Color::rgba(r, g, b, a);
+        sprite.custom_size = Some(Vec2::splat(def.size));
+        *texture = assets.load(def.sprite.as_str());
+    }
 }
 
 fn spawn_mouse_cursor(mut commands: Commands, assets: Res<AssetServer>) {
     let image = assets.load(CURSOR_SPRITE);
 
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: image,
+            ..Default::default()
+        })
+        .insert(Cursor)
+        .insert(Name::new("Cursor"));
+}
+
+pub fn spawn_reticle(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    player: Entity,
+    color: Color,
+) -> Entity {
     commands
         .spawn_bundle(SpriteBundle {
             transform: Transform {
@@ -79,9 +277,194 @@ fn spawn_mouse_cursor(mut commands: Commands, assets: Res<AssetServer>) {
                 scale: Vec3::splat(1.0 / 128.0),
                 ..Default::default()
             },
-            texture: image,
+            sprite: Sprite {
+                color,
+                ..Default::default()
+            },
+            texture: assets.load(CURSOR_SPRITE),
             ..Default::default()
         })
-        .insert(Cursor)
-        .insert(Name::new("Cursor"));
+        .insert(Reticle { player })
+        .insert(Name::new("Reticle"))
+        .id()
+}
+
+fn place_reticles(
+    mut commands: Commands,
+    mut reticles: Query<(Entity, &Reticle, &mut Transform)>,
+    players: Query<&PlayerInput>,
+) {
+    for (entity, reticle, mut transform) in reticles.iter_mut() {
+        match players.get(reticle.player) {
+            Ok(input) => transform.translation = input.aim.extend(0.1),
+            // The player left.
+            Err(_) => commands.entity(entity).despawn(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera_controller::{fit_projection, CAMERA_SIZE};
+    use bevy::math::const_vec2;
+    use bevy::render::camera::ScalingMode;
+    use std::f32::consts::FRAC_PI_2;
+
+    const WINDOW: Vec2 = const_vec2!([1280.0, 720.0]);
+
+    /// Half a screen of 8 by 4.5 units, zoomed in by half.
+    fn zoomed_in() -> OrthographicProjection {
+        OrthographicProjection {
+            left: -8.0,
+            right: 8.0,
+            bottom: -4.5,
+            top: 4.5,
+            scale: 0.5,
+            scaling_mode: ScalingMode::None,
+            ..Default::default()
+        }
+    }
+
+    fn tilted() -> GlobalTransform {
+        GlobalTransform::from_xyz(3.0, -2.0, 999.9).with_rotation(Quat::from_rotation_z(0.7))
+    }
+
+    #[test]
+    fn screen_to_world_and_back() {
+        let (projection, camera) = (zoomed_in(), tilted());
+        for pixel in [
+            Vec2::ZERO,
+            WINDOW / 2.0,
+            WINDOW,
+            Vec2::new(100.0, 650.0),
+            Vec2::new(-40.0, 900.0),
+        ] {
+            let world = screen_to_world(pixel, WINDOW, &projection, &camera);
+            let back = world_to_screen(world, WINDOW, &projection, &camera);
+            assert!(
+                back.distance(pixel) < 1e-2,
+                "{} came back as {}",
+                pixel,
+                back
+            );
+        }
+    }
+
+    #[test]
+    fn world_to_screen_and_back() {
+        let (projection, camera) = (zoomed_in(), tilted());
+        for world in [
+            Vec2::new(3.0, -2.0),
+            Vec2::new(0.0, 0.0),
+            Vec2::new(5.5, -0.25),
+            Vec2::new(-20.0, 13.0),
+        ] {
+            let pixel = world_to_screen(world, WINDOW, &projection, &camera);
+            let back = screen_to_world(pixel, WINDOW, &projection, &camera);
+            assert!(
+                back.distance(world) < 1e-4,
+                "{} came back as {}",
+                world,
+                back
+            );
+        }
+    }
+
+    #[test]
+    fn scale_and_rotation_are_applied() {
+        let projection = zoomed_in();
+        let camera = GlobalTransform::from_xyz(3.0, -2.0, 999.9)
+            .with_rotation(Quat::from_rotation_z(FRAC_PI_2));
+        // Zoomed in by half, the right edge of the screen is 4 units from the centre,
+        // and with the camera turned a quarter to the left that's straight up.
+        let right_edge = screen_to_world(
+            Vec2::new(WINDOW.x, WINDOW.y / 2.0),
+            WINDOW,
+            &projection,
+            &camera,
+        );
+        assert!(
+            right_edge.distance(Vec2::new(3.0, 2.0)) < 1e-4,
+            "{}",
+            right_edge
+        );
+        let centre = world_to_screen(Vec2::new(3.0, -2.0), WINDOW, &projection, &camera);
+        assert!(centre.distance(WINDOW / 2.0) < 1e-2, "{}", centre);
+    }
+
+    /// A projection fitted the way the game camera is, for a window of `size` pixels.
+    fn fitted(size: Vec2) -> OrthographicProjection {
+        let mut projection = OrthographicProjection {
+            scaling_mode: ScalingMode::None,
+            ..Default::default()
+        };
+        fit_projection(&mut projection, CAMERA_SIZE, size.x / size.y);
+        projection
+    }
+
+    fn assert_maps_window(size: Vec2) {
+        let projection = fitted(size);
+        let camera = GlobalTransform::from_xyz(3.0, -2.0, 999.9);
+        let to_world = |pixel: Vec2| screen_to_world(pixel, size, &projection, &camera);
+        let half_width = CAMERA_SIZE * size.x / size.y;
+        let expected = [
+            (size / 2.0, Vec2::new(3.0, -2.0)),
+            (Vec2::ZERO, Vec2::new(3.0 - half_width, -2.0 - CAMERA_SIZE)),
+            (size, Vec2::new(3.0 + half_width, -2.0 + CAMERA_SIZE)),
+            (
+                Vec2::new(size.x, 0.0),
+                Vec2::new(3.0 + half_width, -2.0 - CAMERA_SIZE),
+            ),
+        ];
+        for (pixel, world) in expected {
+            let mapped = to_world(pixel);
+            assert!(
+                mapped.distance(world) < 1e-3,
+                "{:?} at {} mapped to {} instead of {}",
+                size,
+                pixel,
+                mapped,
+                world
+            );
+        }
+        // Square pixels: a pixel covers the same distance across as it does up.
+        let across = to_world(Vec2::new(1.0, 0.0)).x - to_world(Vec2::ZERO).x;
+        let up = to_world(Vec2::new(0.0, 1.0)).y - to_world(Vec2::ZERO).y;
+        assert!((across - up).abs() < 1e-5);
+    }
+
+    #[test]
+    fn screen_to_world_at_4_3() {
+        assert_maps_window(Vec2::new(1024.0, 768.0));
+    }
+
+    #[test]
+    fn screen_to_world_at_16_9() {
+        assert_maps_window(Vec2::new(1920.0, 1080.0));
+    }
+
+    #[test]
+    fn screen_to_world_at_21_9() {
+        assert_maps_window(Vec2::new(2560.0, 1080.0));
+    }
+
+    #[test]
+    fn highest_priority_request_is_on_top() {
+        let mut requests = CursorRequests::default();
+        assert_eq!(requests.top(), None);
+        requests.push(CursorStyle::Crosshair, HOVER_TARGET_PRIORITY);
+        requests.push(CursorStyle::Brush, PLACEMENT_PRIORITY);
+        requests.push(CursorStyle::Hand, HOVER_PICKUP_PRIORITY);
+        assert_eq!(requests.top(), Some(CursorStyle::Brush));
+    }
+
+    #[test]
+    fn ties_go_to_the_first_request() {
+        let mut requests = CursorRequests::default();
+        requests.push(CursorStyle::Invalid, PLACEMENT_PRIORITY);
+        requests.push(CursorStyle::Brush, PLACEMENT_PRIORITY);
+        requests.push(CursorStyle::Hand, HOVER_PICKUP_PRIORITY);
+        assert_eq!(requests.top(), Some(CursorStyle::Invalid));
+    }
 }
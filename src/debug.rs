@@ -1,95 +1,298 @@
 #![allow(dead_code)]
 
-use bevy::ecs::system::lifetimeless::SRes;
-use bevy::ecs::system::SystemParamItem;
-use bevy::math::Vec3Swizzles;
+use bevy::ecs::system::lifetimeless::{SRes, SResMut};
+use bevy::ecs::system::{SystemParam, SystemParamItem};
+use bevy::math::{Mat2, Vec3Swizzles};
 use bevy::prelude::*;
 use bevy::reflect::TypeUuid;
-use bevy::render::render_asset::RenderAsset;
+use bevy::render::render_asset::{PrepareAssetLabel, RenderAsset, RenderAssets};
 use bevy::render::render_resource::std430::AsStd430;
 use bevy::render::render_resource::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingType, BufferBindingType, ShaderStages, StorageBuffer,
+    BindGroupLayoutEntry, BindingType, BufferBindingType, BufferId, ShaderStages, StorageBuffer,
 };
 use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::view::RenderLayers;
+use bevy::render::{RenderApp, RenderStage};
 use bevy::sprite::{Material2d, Material2dPipeline, Material2dPlugin, MaterialMesh2dBundle};
+use std::marker::PhantomData;
 
-use crate::camera_controller::{CameraFollower, CameraMoved, CAMERA_SIZE};
+use crate::camera_controller::{CameraFollower, CameraMoved};
+use crate::split_screen::PlayerView;
+
+/// Radius of the ring drawn by [`DebugPainter::point`].
+const POINT_RADIUS: f32 = 0.05;
+/// Angle between an arrow's shaft and each side of its head.
+const ARROW_HEAD_ANGLE: f32 = 0.5;
+const MAX_ARROW_HEAD: f32 = 0.2;
 
 pub struct DebugPlugin;
 
 impl Plugin for DebugPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ActiveCircles>()
+            .init_resource::<ActiveLines>()
+            .init_resource::<DebugShapes>()
             .add_plugin(Material2dPlugin::<DebugMaterial>::default())
-            .add_startup_system(spawn_debug_overlay)
-            .add_system(update_debug_overlay.after(CameraMoved));
+            .init_resource::<DebugOverlay>()
+            .add_system(spawn_debug_overlays)
+            // After everything in Update has had its chance to draw this frame.
+            .add_system_to_stage(CoreStage::PostUpdate, update_debug_overlay)
+            .add_system(fit_debug_overlay.after(CameraMoved));
+
+        app.sub_app_mut(RenderApp)
+            .init_resource::<ActiveCircles>()
+            .init_resource::<ActiveLines>()
+            .init_resource::<DebugBuffers>()
+            .add_system_to_stage(RenderStage::Extract, extract_debug_shapes)
+            .add_system_to_stage(
+                RenderStage::Prepare,
+                prepare_debug_buffers.after(PrepareAssetLabel::AssetPrepare),
+            );
     }
 }
 
 fn update_debug_overlay(
-    mut materials: ResMut<Assets<DebugMaterial>>,
-    mut overlay: Query<&mut Handle<DebugMaterial>>,
+    mut active_circles: ResMut<ActiveCircles>,
+    mut active_lines: ResMut<ActiveLines>,
+    mut shapes: ResMut<DebugShapes>,
     circles: Query<(&Transform, &DebugCircle)>,
     rects: Query<(&Transform, &DebugRect)>,
+    lines: Query<&DebugLine>,
+    time: Res<Time>,
 ) {
-    for mut overlay in overlay.iter_mut() {
-        *overlay = materials.add(DebugMaterial {
-            active_circles: ActiveCircles(
-                circles
-                    .iter()
-                    .map(|(tf, c)| DebugCircleData {
-                        color: c.color.as_linear_rgba_f32().into(),
-                        center: tf.translation,
-                        radius: c.radius,
-                    })
-                    .collect(),
-            ),
-            active_lines: ActiveLines(
-                rects
-                    .iter()
-                    .flat_map(|(tf, r)| {
-                        [
-                            (r, r.top_left(tf), r.top_right(tf)),
-                            (r, r.top_right(tf), r.bottom_right(tf)),
-                            (r, r.bottom_right(tf), r.bottom_left(tf)),
-                            (r, r.bottom_left(tf), r.top_left(tf)),
-                        ]
-                    })
-                    .map(|(r, start, end)| DebugLineData {
-                        color: r.color.as_linear_rgba_f32().into(),
-                        start,
-                        end,
-                    })
-                    .collect(),
-            ),
-        });
+    active_circles.clear();
+    active_circles.extend(circles.iter().map(|(tf, c)| DebugCircleData {
+        color: c.color.as_linear_rgba_f32().into(),
+        center: tf.translation,
+        radius: c.radius,
+    }));
+    active_circles.extend(shapes.circles.iter().map(|(c, _)| *c));
+
+    active_lines.clear();
+    active_lines.extend(
+        rects
+            .iter()
+            .flat_map(|(tf, r)| {
+                [
+                    (r, r.top_left(tf), r.top_right(tf)),
+                    (r, r.top_right(tf), r.bottom_right(tf)),
+                    (r, r.bottom_right(tf), r.bottom_left(tf)),
+                    (r, r.bottom_left(tf), r.top_left(tf)),
+                ]
+            })
+            .map(|(r, start, end)| DebugLineData {
+                color: r.color.as_linear_rgba_f32().into(),
+                start,
+                end,
+            }),
+    );
+    active_lines.extend(lines.iter().map(|l| DebugLineData {
+        color: l.color.as_linear_rgba_f32().into(),
+        start: l.start,
+        end: l.end,
+    }));
+    active_lines.extend(shapes.lines.iter().map(|(l, _)| *l));
+
+    shapes.expire(time.seconds_since_startup());
+}
+
+/// What every view's overlay draws with. The overlays share one material, since the
+/// shapes live in buffers bound to it.
+struct DebugOverlay {
+    mesh: Handle<Mesh>,
+    material: Handle<DebugMaterial>,
+}
+
+impl FromWorld for DebugOverlay {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Mesh::from(shape::Quad::new(Vec2::ONE)));
+        let material = world
+            .resource_mut::<Assets<DebugMaterial>>()
+            .add(DebugMaterial);
+        DebugOverlay { mesh, material }
     }
 }
 
-fn spawn_debug_overlay(
+/// Gives each new view an overlay that only it can see.
+fn spawn_debug_overlays(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<DebugMaterial>>,
+    overlay: Res<DebugOverlay>,
+    views: Query<(Entity, &PlayerView), Added<PlayerView>>,
 ) {
-    commands
-        .spawn()
-        .insert_bundle(MaterialMesh2dBundle {
-            mesh: meshes
-                .add(Mesh::from(shape::Quad::new(Vec2::new(
-                    CAMERA_SIZE * 4.0,
-                    CAMERA_SIZE * 2.0,
-                ))))
-                .into(),
-            material: materials.add(DebugMaterial {
-                active_circles: ActiveCircles(Vec::new()),
-                active_lines: ActiveLines(Vec::new()),
-            }),
-            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 990.0)),
-            ..Default::default()
-        })
-        .insert(CameraFollower)
-        .insert(Name::new("Debug layer"));
+    for (camera, view) in views.iter() {
+        commands
+            .spawn()
+            .insert_bundle(MaterialMesh2dBundle {
+                mesh: overlay.mesh.clone().into(),
+                material: overlay.material.clone(),
+                transform: Transform::from_translation(Vec3::new(0.0, 0.0, 990.0)),
+                ..Default::default()
+            })
+            .insert(RenderLayers::layer(view.layer()))
+            .insert(CameraFollower { camera })
+            .insert(Name::new(format!("Player {} Debug Layer", view.index + 1)));
+    }
+}
+
+/// Stretches each overlay's unit quad over whatever its camera currently shows.
+fn fit_debug_overlay(
+    mut overlays: Query<(&CameraFollower, &mut Transform), With<Handle<DebugMaterial>>>,
+    cameras: Query<&OrthographicProjection>,
+) {
+    for (follower, mut tf) in overlays.iter_mut() {
+        let projection = match cameras.get(follower.camera) {
+            Ok(projection) => projection,
+            Err(_) => continue,
+        };
+        let scale = (projection.scale
+            * Vec2::new(
+                projection.right - projection.left,
+                projection.top - projection.bottom,
+            ))
+        .extend(1.0);
+        if tf.scale != scale {
+            tf.scale = scale;
+        }
+    }
+}
+
+/// Shapes drawn through [`DebugDraw`], each with the time it disappears at.
+/// `None` means it only lasts for the frame it was drawn in.
+#[derive(Default)]
+pub struct DebugShapes {
+    circles: Vec<(DebugCircleData, Option<f64>)>,
+    lines: Vec<(DebugLineData, Option<f64>)>,
+}
+
+impl DebugShapes {
+    fn expire(&mut self, now: f64) {
+        let alive = |expires: &Option<f64>| expires.is_some_and(|expires| expires > now);
+        self.circles.retain(|(_, expires)| alive(expires));
+        self.lines.retain(|(_, expires)| alive(expires));
+    }
+}
+
+/// Immediate-mode debug drawing, for visualising things like rays, paths and
+/// velocities without spawning components for them. Shapes last for the frame
+/// they're drawn in, or as long as asked for with [`DebugDraw::timed`].
+#[derive(SystemParam)]
+pub struct DebugDraw<'w, 's> {
+    shapes: ResMut<'w, DebugShapes>,
+    time: Res<'w, Time>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> DebugDraw<'w, 's> {
+    /// Draws shapes that stay up for `seconds`.
+    pub fn timed(&mut self, seconds: f32) -> DebugPainter<'_> {
+        DebugPainter {
+            expires: Some(self.time.seconds_since_startup() + seconds as f64),
+            shapes: &mut self.shapes,
+        }
+    }
+
+    fn painter(&mut self) -> DebugPainter<'_> {
+        DebugPainter {
+            shapes: &mut self.shapes,
+            expires: None,
+        }
+    }
+
+    pub fn line(&mut self, start: Vec2, end: Vec2, color: Color) {
+        self.painter().line(start, end, color);
+    }
+
+    pub fn circle(&mut self, center: Vec2, radius: f32, color: Color) {
+        self.painter().circle(center, radius, color);
+    }
+
+    pub fn rect(&mut self, center: Vec2, size: Vec2, color: Color) {
+        self.painter().rect(center, size, color);
+    }
+
+    pub fn arrow(&mut self, start: Vec2, end: Vec2, color: Color) {
+        self.painter().arrow(start, end, color);
+    }
+
+    pub fn polyline(&mut self, points: impl IntoIterator<Item = Vec2>, color: Color) {
+        self.painter().polyline(points, color);
+    }
+
+    pub fn point(&mut self, position: Vec2, color: Color) {
+        self.painter().point(position, color);
+    }
+}
+
+/// Draws shapes that all disappear at the same time.
+pub struct DebugPainter<'a> {
+    shapes: &'a mut DebugShapes,
+    expires: Option<f64>,
+}
+
+impl<'a> DebugPainter<'a> {
+    pub fn line(&mut self, start: Vec2, end: Vec2, color: Color) -> &mut Self {
+        let line = DebugLineData {
+            color: color.as_linear_rgba_f32().into(),
+            start,
+            end,
+        };
+        self.shapes.lines.push((line, self.expires));
+        self
+    }
+
+    /// Drawn as a ring, like [`DebugCircle`].
+    pub fn circle(&mut self, center: Vec2, radius: f32, color: Color) -> &mut Self {
+        let circle = DebugCircleData {
+            color: color.as_linear_rgba_f32().into(),
+            center: center.extend(0.0),
+            radius,
+        };
+        self.shapes.circles.push((circle, self.expires));
+        self
+    }
+
+    pub fn rect(&mut self, center: Vec2, size: Vec2, color: Color) -> &mut Self {
+        let half = size / 2.0;
+        let corners = [
+            center + Vec2::new(-half.x, half.y),
+            center + half,
+            center + Vec2::new(half.x, -half.y),
+            center - half,
+        ];
+        self.polyline(corners.into_iter().chain([corners[0]]), color)
+    }
+
+    pub fn arrow(&mut self, start: Vec2, end: Vec2, color: Color) -> &mut Self {
+        self.line(start, end, color);
+        let shaft = end - start;
+        let head = (0.3 * shaft.length()).min(MAX_ARROW_HEAD);
+        let back = -shaft.normalize_or_zero() * head;
+        for angle in [ARROW_HEAD_ANGLE, -ARROW_HEAD_ANGLE] {
+            self.line(end, end + Mat2::from_angle(angle) * back, color);
+        }
+        self
+    }
+
+    /// Joins each point to the next.
+    pub fn polyline(&mut self, points: impl IntoIterator<Item = Vec2>, color: Color) -> &mut Self {
+        let mut points = points.into_iter();
+        if let Some(mut previous) = points.next() {
+            for point in points {
+                self.line(previous, point, color);
+                previous = point;
+            }
+        }
+        self
+    }
+
+    /// A small ring, since the overlay can't fill shapes.
+    pub fn point(&mut self, position: Vec2, color: Color) -> &mut Self {
+        self.circle(position, POINT_RADIUS, color)
+    }
 }
 
 #[derive(Component, Clone, Copy)]
@@ -149,6 +352,13 @@ impl DebugRect {
     }
 }
 
+#[derive(Component, Clone, Copy)]
+pub struct DebugLine {
+    pub color: Color,
+    pub start: Vec2,
+    pub end: Vec2,
+}
+
 #[derive(Debug, Clone, Copy, Default, AsStd430)]
 pub struct DebugLineData {
     pub color: Vec4,
@@ -164,13 +374,107 @@ pub struct ActiveLines(Vec<DebugLineData>);
 
 pub struct GpuDebugMaterial {
     pub bind_group: BindGroup,
+    /// The buffers the bind group was made for, to tell when they've been replaced.
+    buffers: (BufferId, BufferId),
 }
 
+/// The overlay's shapes live in [`DebugBuffers`] rather than in the material, so that
+/// the material only has to be created once.
 #[derive(Debug, Clone, Default, TypeUuid)]
 #[uuid = "0b1ad73c-8919-48f5-8e19-d05292791f47"]
-pub struct DebugMaterial {
-    active_circles: ActiveCircles,
-    active_lines: ActiveLines,
+pub struct DebugMaterial;
+
+/// Storage buffers holding the overlay's shapes, in the render world. Each starts with
+/// the number of shapes in it, in the `x` of a 16 byte header so that the shapes sit where
+/// the shader expects them, and is rewritten in place every frame. They're only
+/// reallocated when they need to grow, which is rounded up to a power of two.
+#[derive(Default)]
+pub struct DebugBuffers {
+    circles: StorageBuffer<DebugCircleData, UVec4>,
+    lines: StorageBuffer<DebugLineData, UVec4>,
+}
+
+impl DebugBuffers {
+    fn write(
+        &mut self,
+        circles: &[DebugCircleData],
+        lines: &[DebugLineData],
+        device: &RenderDevice,
+        queue: &RenderQueue,
+    ) {
+        fill(&mut self.circles, circles);
+        fill(&mut self.lines, lines);
+        self.circles.write_buffer(device, queue);
+        self.lines.write_buffer(device, queue);
+    }
+
+    fn ids(&self) -> Option<(BufferId, BufferId)> {
+        Some((self.circles.buffer()?.id(), self.lines.buffer()?.id()))
+    }
+
+    fn bind_group(&self, device: &RenderDevice, layout: &BindGroupLayout) -> Option<BindGroup> {
+        Some(device.create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.circles.binding()?,
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.lines.binding()?,
+                },
+            ],
+            label: Some("debug_material_bind_group"),
+            layout,
+        }))
+    }
+}
+
+/// Pads with empty shapes up to a power of two, which the shader skips past using the count.
+fn fill<T: AsStd430 + Copy + Default>(buffer: &mut StorageBuffer<T, UVec4>, values: &[T]) {
+    buffer.clear();
+    buffer.set_body(UVec4::new(values.len() as u32, 0, 0, 0));
+    for &value in values {
+        buffer.push(value);
+    }
+    for _ in values.len()..values.len().next_power_of_two() {
+        buffer.push(T::default());
+    }
+}
+
+fn extract_debug_shapes(
+    mut commands: Commands,
+    circles: Res<ActiveCircles>,
+    lines: Res<ActiveLines>,
+) {
+    commands.insert_resource(circles.clone());
+    commands.insert_resource(lines.clone());
+}
+
+fn prepare_debug_buffers(
+    mut buffers: ResMut<DebugBuffers>,
+    mut materials: ResMut<RenderAssets<DebugMaterial>>,
+    circles: Res<ActiveCircles>,
+    lines: Res<ActiveLines>,
+    pipeline: Res<Material2dPipeline<DebugMaterial>>,
+    device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+) {
+    buffers.write(&circles, &lines, &device, &queue);
+
+    let ids = match buffers.ids() {
+        Some(ids) => ids,
+        None => return,
+    };
+    for material in materials.values_mut() {
+        if material.buffers == ids {
+            continue;
+        }
+        if let Some(bind_group) = buffers.bind_group(&device, &pipeline.material2d_layout) {
+            material.bind_group = bind_group;
+            material.buffers = ids;
+        }
+    }
 }
 
 impl RenderAsset for DebugMaterial {
@@ -180,6 +484,7 @@ impl RenderAsset for DebugMaterial {
         SRes<RenderDevice>,
         SRes<Material2dPipeline<Self>>,
         SRes<RenderQueue>,
+        SResMut<DebugBuffers>,
     );
 
     fn extract_asset(&self) -> Self::ExtractedAsset {
@@ -187,39 +492,25 @@ impl RenderAsset for DebugMaterial {
     }
 
     fn prepare_asset(
-        mut material: Self,
-        (render_device, pipeline, queue): &mut SystemParamItem<Self::Param>,
+        material: Self,
+        (render_device, pipeline, queue, buffers): &mut SystemParamItem<Self::Param>,
     ) -> Result<
         Self::PreparedAsset,
         bevy::render::render_asset::PrepareAssetError<Self::ExtractedAsset>,
     > {
-        //println!("Debug circles: {}", material.active_circles.len());
-        //println!("Debug lines: {}", material.active_lines.len());
-
-        let mut circle_storage = StorageBuffer::<DebugCircleData>::default();
-        circle_storage.append(&mut material.active_circles);
-        circle_storage.write_buffer(render_device, queue);
-
-        let mut line_storage = StorageBuffer::<DebugLineData>::default();
-        line_storage.append(&mut material.active_lines);
-        line_storage.write_buffer(render_device, queue);
-
-        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: circle_storage.binding().unwrap(),
-                },
-                BindGroupEntry {
-                    binding: 1,
-                    resource: line_storage.binding().unwrap(),
-                },
-            ],
-            label: Some("debug_material_bind_group"),
-            layout: &pipeline.material2d_layout,
-        });
-
-        Ok(GpuDebugMaterial { bind_group })
+        if buffers.ids().is_none() {
+            buffers.write(&[], &[], render_device, queue);
+        }
+        match (
+            buffers.bind_group(render_device, &pipeline.material2d_layout),
+            buffers.ids(),
+        ) {
+            (Some(bind_group), Some(buffers)) => Ok(GpuDebugMaterial {
+                bind_group,
+                buffers,
+            }),
+            _ => Err(bevy::render::render_asset::PrepareAssetError::RetryNextUpdate(material)),
+        }
     }
 }
 
@@ -263,3 +554,60 @@ impl Material2d for DebugMaterial {
 }
 
 pub type DebugMesh2dBundle = MaterialMesh2dBundle<DebugMaterial>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::split_screen::PlayerView;
+    use bevy::asset::AssetPlugin;
+
+    fn draw_every_frame(mut draw: DebugDraw) {
+        draw.line(Vec2::ZERO, Vec2::X, Color::RED);
+        draw.circle(Vec2::Y, 0.5, Color::GREEN);
+        draw.timed(0.1).rect(Vec2::ZERO, Vec2::ONE, Color::BLUE);
+    }
+
+    #[test]
+    fn overlay_keeps_one_material_across_frames() {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin)
+            .add_asset::<Mesh>()
+            .add_asset::<DebugMaterial>()
+            .init_resource::<ActiveCircles>()
+            .init_resource::<ActiveLines>()
+            .init_resource::<DebugShapes>()
+            .init_resource::<DebugOverlay>()
+            .add_system(draw_every_frame)
+            .add_system(spawn_debug_overlays)
+            .add_system(fit_debug_overlay)
+            .add_system_to_stage(CoreStage::PostUpdate, update_debug_overlay);
+
+        // Two players' views, each with an overlay of its own.
+        for index in 0..2 {
+            let player = app.world.spawn().id();
+            let pane = app.world.spawn().id();
+            app.world
+                .spawn()
+                .insert(PlayerView::new(player, index, pane))
+                .insert(OrthographicProjection::default());
+        }
+        app.world
+            .spawn()
+            .insert(Transform::default())
+            .insert(DebugCircle::default());
+
+        for _ in 0..100 {
+            app.update();
+        }
+
+        assert_eq!(app.world.resource::<Assets<DebugMaterial>>().len(), 1);
+        let overlays = app
+            .world
+            .query_filtered::<(), With<Handle<DebugMaterial>>>()
+            .iter(&app.world)
+            .count();
+        assert_eq!(overlays, 2);
+        assert_eq!(app.world.resource::<ActiveCircles>().len(), 2);
+    }
+}
@@ -0,0 +1,276 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::render::camera::OrthographicProjection;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use bevy_inspector_egui::Inspectable;
+use rand::seq::SliceRandom;
+
+use crate::enemy::{spawn_enemy, Enemy, EnemyKind};
+use crate::rock::{spawn_rock, Rock, RockSize};
+use crate::spawner::SpawnedBy;
+use crate::split_screen::PlayerView;
+use crate::tilemap::TileMap;
+
+/// Seconds between clearing a wave and the next one arriving.
+const INTERMISSION: f32 = 5.0;
+/// Extra distance outside the view that rocks and enemies must spawn at.
+const OFFSCREEN_MARGIN: f32 = 0.5;
+const ROCK_DRIFT_SPEED: f32 = 0.6;
+
+pub struct DirectorPlugin;
+
+impl Plugin for DirectorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WaveState>()
+            .add_event::<WaveStarted>()
+            .add_event::<WaveCleared>()
+            .add_system(run_waves.label(WavesUpdated))
+            .add_system(wave_hud.after(WavesUpdated));
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct WavesUpdated;
+
+#[derive(Inspectable, Debug, Clone)]
+pub struct WaveState {
+    /// The current wave, 0 before the first one has started.
+    pub number: u32,
+    /// Rocks and enemies from this wave still alive, including fragments of the rocks
+    /// spawned. Whatever map spawners produce doesn't count.
+    pub remaining: u32,
+    /// Seconds until the next wave, or `None` while a wave is in progress.
+    pub time_to_next: Option<f32>,
+}
+
+impl Default for WaveState {
+    fn default() -> Self {
+        WaveState {
+            number: 0,
+            remaining: 0,
+            time_to_next: Some(2.0),
+        }
+    }
+}
+
+pub struct WaveStarted {
+    pub number: u32,
+    pub rocks: u32,
+    pub enemies: u32,
+}
+
+pub struct WaveCleared {
+    pub number: u32,
+}
+
+struct WaveComposition {
+    rocks: u32,
+    /// Chance that each rock is large rather than medium.
+    large_chance: f32,
+    chasers: u32,
+    shooters: u32,
+}
+
+/// What wave `number` sends. Chasers join from the second wave, shooters from the third.
+fn wave_composition(number: u32) -> WaveComposition {
+    WaveComposition {
+        rocks: 4 + 2 * number,
+        large_chance: (0.15 * number as f32).min(0.8),
+        chasers: number.saturating_sub(1),
+        shooters: number.saturating_sub(1) / 2,
+    }
+}
+
+/// The area of the world a camera currently shows.
+fn camera_view(tf: &Transform, projection: &OrthographicProjection) -> Rect<f32> {
+    let scale = projection.scale * tf.scale.xy();
+    let center = tf.translation.xy();
+    Rect {
+        left: center.x + projection.left * scale.x,
+        right: center.x + projection.right * scale.x,
+        top: center.y + projection.top * scale.y,
+        bottom: center.y + projection.bottom * scale.y,
+    }
+}
+
+fn view_center(view: &Rect<f32>) -> Vec2 {
+    Vec2::new(
+        (view.left + view.right) / 2.0,
+        (view.top + view.bottom) / 2.0,
+    )
+}
+
+/// Walkable tiles outside every view. If the views cover the whole map, falls back
+/// to the walkable tiles furthest from the nearest view's centre.
+fn spawn_tiles(tilemap: &TileMap, views: &[Rect<f32>]) -> Vec<Vec2> {
+    let walkable: Vec<Vec2> = tilemap
+        .iter()
+        .filter(|(_, kind)| kind.is_walkable())
+        .map(|(pos, _)| pos.as_vec2())
+        .collect();
+
+    let offscreen: Vec<Vec2> = walkable
+        .iter()
+        .copied()
+        .filter(|p| {
+            views.iter().all(|view| {
+                p.x < view.left - OFFSCREEN_MARGIN
+                    || p.x > view.right + OFFSCREEN_MARGIN
+                    || p.y < view.bottom - OFFSCREEN_MARGIN
+                    || p.y > view.top + OFFSCREEN_MARGIN
+            })
+        })
+        .collect();
+    if !offscreen.is_empty() {
+        return offscreen;
+    }
+
+    let distance = |p: &Vec2| {
+        views
+            .iter()
+            .map(|view| p.distance(view_center(view)))
+            .fold(f32::INFINITY, f32::min)
+    };
+    let furthest = walkable.iter().map(distance).fold(0.0, f32::max);
+    walkable
+        .into_iter()
+        .filter(|p| distance(p) >= furthest - 1.0)
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_waves(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut state: ResMut<WaveState>,
+    mut started: EventWriter<WaveStarted>,
+    mut cleared: EventWriter<WaveCleared>,
+    rocks: Query<(), (With<Rock>, Without<SpawnedBy>)>,
+    enemies: Query<(), (With<Enemy>, Without<SpawnedBy>)>,
+    cameras: Query<(&Transform, &OrthographicProjection), With<PlayerView>>,
+    tilemap: Res<TileMap>,
+    keyboard: Res<Input<KeyCode>>,
+    time: Res<Time>,
+) {
+    state.remaining = (rocks.iter().count() + enemies.iter().count()) as u32;
+
+    match state.time_to_next {
+        None if state.remaining == 0 => {
+            cleared.send(WaveCleared {
+                number: state.number,
+            });
+            state.time_to_next = Some(INTERMISSION);
+        }
+        Some(time_to_next) => state.time_to_next = Some(time_to_next - time.delta_seconds()),
+        None => {}
+    }
+
+    // Space is a debug shortcut that brings in the next wave right away.
+    let due = state.time_to_next.is_some_and(|t| t <= 0.0);
+    if !due && !keyboard.just_pressed(KeyCode::Space) {
+        return;
+    }
+
+    let views: Vec<_> = cameras
+        .iter()
+        .map(|(tf, projection)| camera_view(tf, projection))
+        .collect();
+    if views.is_empty() {
+        return;
+    }
+    let tiles = spawn_tiles(&tilemap, &views);
+    if tiles.is_empty() {
+        return;
+    }
+
+    state.number += 1;
+    state.time_to_next = None;
+    let wave = wave_composition(state.number);
+    let mut rng = rand::thread_rng();
+    let mut spawn_point = || {
+        let point = tiles
+            .choose(&mut rng)
+            .map(|tile| *tile + Vec2::new(rand::random(), rand::random()) - Vec2::splat(0.5));
+        if point.is_none() {
+            warn!("No tile to spawn wave {} on", state.number);
+        }
+        point
+    };
+    for position in (0..wave.rocks).filter_map(|_| spawn_point()) {
+        let size = if rand::random::<f32>() < wave.large_chance {
+            RockSize::Large
+        } else {
+            RockSize::Medium
+        };
+        // Drift into whichever view is closest.
+        let target = views
+            .iter()
+            .map(view_center)
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)))
+            .unwrap();
+        let velocity = ROCK_DRIFT_SPEED * (target - position).normalize_or_zero();
+        let spin = 2.0 * rand::random::<f32>() - 1.0;
+        spawn_rock(&mut commands, &assets, size, position, velocity, spin);
+    }
+    for position in (0..wave.chasers).filter_map(|_| spawn_point()) {
+        spawn_enemy(&mut commands, &assets, EnemyKind::Chaser, position);
+    }
+    for position in (0..wave.shooters).filter_map(|_| spawn_point()) {
+        spawn_enemy(&mut commands, &assets, EnemyKind::Shooter, position);
+    }
+    let enemies = wave.chasers + wave.shooters;
+    state.remaining += wave.rocks + enemies;
+
+    started.send(WaveStarted {
+        number: state.number,
+        rocks: wave.rocks,
+        enemies,
+    });
+}
+
+fn wave_hud(
+    mut egui_context: ResMut<EguiContext>,
+    state: Res<WaveState>,
+    mut started: EventReader<WaveStarted>,
+    mut cleared: EventReader<WaveCleared>,
+    mut banner: Local<Option<(f64, String)>>,
+    time: Res<Time>,
+) {
+    let now = time.seconds_since_startup();
+    for wave in started.iter() {
+        let text = if wave.enemies > 0 {
+            format!(
+                "Wave {}: {} rocks and {} enemies incoming!",
+                wave.number, wave.rocks, wave.enemies
+            )
+        } else {
+            format!("Wave {}: {} rocks incoming!", wave.number, wave.rocks)
+        };
+        *banner = Some((now, text));
+    }
+    for wave in cleared.iter() {
+        *banner = Some((now, format!("Wave {} cleared", wave.number)));
+    }
+    if banner.as_ref().is_some_and(|(since, _)| now - since > 3.0) {
+        *banner = None;
+    }
+
+    egui::Window::new("Wave")
+        .anchor(egui::Align2::CENTER_TOP, [0.0, 10.0])
+        .title_bar(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            if let Some((_, text)) = banner.as_ref() {
+                ui.heading(text);
+            }
+            match state.time_to_next {
+                Some(time_to_next) => ui.label(format!(
+                    "Wave {} in {:.0}s",
+                    state.number + 1,
+                    time_to_next.max(0.0).ceil()
+                )),
+                None => ui.label(format!("Wave {}: {} left", state.number, state.remaining)),
+            };
+        });
+}
@@ -0,0 +1,242 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+use bevy_inspector_egui::Inspectable;
+use serde::Deserialize;
+
+use crate::ai::Ai;
+use crate::collision::{Collider, CollisionEvent, CollisionsDetected, Layers};
+use crate::debug::DebugCircle;
+use crate::player::{nearest_player, Knockback, Player, PlayerDamaged};
+use crate::raycast::ray_grid;
+use crate::rock::Velocity;
+use crate::selection::Selectable;
+use crate::steering::{Steered, Steering};
+use crate::tilemap::TileMap;
+use crate::weapon::{rotation_towards, spawn_laser, Hit, HitsResolved, Laser};
+
+const ENEMY_SPRITE: &str = "compass.png";
+const LASER_SPRITE: &str = "laser.png";
+
+const ENEMY_RADIUS: f32 = 0.2;
+const EXPLOSION_KNOCKBACK: f32 = 5.0;
+
+pub struct EnemyPlugin;
+
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(fire_at_player.after(Steered))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                explode_chasers
+                    .after(CollisionsDetected)
+                    .before(PlayerDamaged),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, damage_enemies.after(HitsResolved));
+    }
+}
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EnemyKind {
+    /// Runs straight at the player and blows up on contact.
+    #[default]
+    Chaser,
+    /// Keeps its distance and fires lasers at the player.
+    Shooter,
+}
+
+impl EnemyKind {
+    fn color(&self) -> Color {
+        match self {
+            EnemyKind::Chaser => Color::ORANGE_RED,
+            EnemyKind::Shooter => Color::YELLOW,
+        }
+    }
+
+    pub fn hit_points(&self) -> f32 {
+        match self {
+            EnemyKind::Chaser => 1.0,
+            EnemyKind::Shooter => 2.0,
+        }
+    }
+
+    /// Speeds only; which behaviours are blended is up to the AI state.
+    fn steering(&self) -> Steering {
+        match self {
+            EnemyKind::Chaser => Steering::new(2.5, 8.0, ENEMY_RADIUS),
+            EnemyKind::Shooter => Steering::new(1.8, 6.0, ENEMY_RADIUS),
+        }
+    }
+
+    /// Name of the state machine in `assets/ai.ron` that drives this kind.
+    fn state_machine(&self) -> &'static str {
+        match self {
+            EnemyKind::Chaser => "chaser",
+            EnemyKind::Shooter => "shooter",
+        }
+    }
+}
+
+#[derive(Component, Inspectable)]
+pub struct Enemy {
+    pub kind: EnemyKind,
+    pub hit_points: f32,
+}
+
+/// Lets an enemy shoot at the player when it can see them.
+#[derive(Component, Inspectable)]
+pub struct Gun {
+    pub range: f32,
+    pub damage: f32,
+    /// Seconds between shots.
+    pub cooldown: f32,
+    ready_in: f32,
+}
+
+impl Default for Gun {
+    fn default() -> Self {
+        Gun {
+            range: 6.0,
+            damage: 1.0,
+            cooldown: 1.5,
+            ready_in: 1.5,
+        }
+    }
+}
+
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    kind: EnemyKind,
+    position: Vec2,
+) -> Entity {
+    let mut enemy = commands.spawn_bundle(SpriteBundle {
+        transform: Transform {
+            translation: position.extend(0.5),
+            scale: Vec3::splat(1.0 / 20.0),
+            ..Default::default()
+        },
+        texture: assets.load(ENEMY_SPRITE),
+        sprite: Sprite {
+            color: kind.color(),
+            ..Default::default()
+        },
+        ..Default::default()
+    });
+    enemy
+        .insert(Enemy {
+            kind,
+            hit_points: kind.hit_points(),
+        })
+        .insert(kind.steering())
+        .insert(Ai::new(kind.state_machine(), position))
+        .insert(Velocity::default())
+        .insert(Collider {
+            radius: ENEMY_RADIUS,
+            layers: Layers::ENEMY,
+            mask: Layers::PLAYER,
+        })
+        .insert(DebugCircle {
+            color: kind.color(),
+            radius: ENEMY_RADIUS,
+        })
+        .insert(Selectable)
+        .insert(Name::new(format!("{:?}", kind)));
+    if kind == EnemyKind::Shooter {
+        enemy.insert(Gun::default());
+    }
+    enemy.id()
+}
+
+fn fire_at_player(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut guns: Query<(&mut Gun, &Transform, Option<&Ai>), With<Enemy>>,
+    players: Query<&Transform, With<Player>>,
+    tilemap: Res<TileMap>,
+    time: Res<Time>,
+) {
+    for (mut gun, transform, ai) in guns.iter_mut() {
+        gun.ready_in -= time.delta_seconds();
+        if gun.ready_in > 0.0 || !ai.is_none_or(|ai| ai.attacking) {
+            continue;
+        }
+
+        let position = transform.translation.xy();
+        let player = match nearest_player(players.iter().map(|tf| tf.translation.xy()), position) {
+            Some(player) => player,
+            None => return,
+        };
+        let offset = player - position;
+        let distance = offset.length();
+        if distance > gun.range || distance <= f32::EPSILON {
+            continue;
+        }
+        let direction = offset / distance;
+        if ray_grid(position, direction, distance, |tile| tilemap.is_solid(tile)).is_some() {
+            continue;
+        }
+
+        gun.ready_in = gun.cooldown;
+        spawn_laser(
+            &mut commands,
+            assets.load(LASER_SPRITE),
+            position,
+            rotation_towards(direction),
+            Laser::new(gun.damage, None, time.time_since_startup()),
+            Layers::PLAYER | Layers::TERRAIN,
+        );
+    }
+}
+
+fn explode_chasers(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    mut hits: EventWriter<Hit>,
+    enemies: Query<(&Enemy, &Transform)>,
+    mut players: Query<(&Transform, &mut Knockback), With<Player>>,
+) {
+    let mut exploded = HashSet::new();
+    for collision in collisions.iter() {
+        let (enemy, enemy_tf) = match enemies.get(collision.entity) {
+            Ok(enemy) => enemy,
+            Err(_) => continue,
+        };
+        if enemy.kind != EnemyKind::Chaser || exploded.contains(&collision.entity) {
+            continue;
+        }
+        let (player_tf, mut knockback) = match players.get_mut(collision.other) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        exploded.insert(collision.entity);
+
+        commands.entity(collision.entity).despawn_recursive();
+        hits.send(Hit {
+            target: collision.other,
+            damage: 1.0,
+        });
+        let away = (player_tf.translation.xy() - enemy_tf.translation.xy()).normalize_or_zero();
+        **knockback = away * EXPLOSION_KNOCKBACK;
+    }
+}
+
+fn damage_enemies(
+    mut commands: Commands,
+    mut hits: EventReader<Hit>,
+    mut enemies: Query<&mut Enemy>,
+) {
+    let mut killed = HashSet::new();
+    for hit in hits.iter() {
+        if killed.contains(&hit.target) {
+            continue;
+        }
+        if let Ok(mut enemy) = enemies.get_mut(hit.target) {
+            enemy.hit_points -= hit.damage;
+            if enemy.hit_points <= 0.0 {
+                killed.insert(hit.target);
+                commands.entity(hit.target).despawn_recursive();
+            }
+        }
+    }
+}
@@ -0,0 +1,629 @@
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use bevy_inspector_egui::Inspectable;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::player::{Controls, Health, Player, MAX_PLAYERS};
+use crate::split_screen::PlayerView;
+use crate::weapon::Energy;
+
+const SAVE_DIR: &str = "saves";
+/// Player one's save. Everyone else's is numbered, like `saves/inventory_2.ron`.
+const INVENTORY_SAVE: &str = "saves/inventory.ron";
+
+/// How much energy using an energy cell restores.
+const ENERGY_CELL_CHARGE: f32 = 25.0;
+/// How much health using a medkit restores.
+const MEDKIT_HEAL: f32 = 2.0;
+
+pub struct InventoryPlugin;
+
+impl Plugin for InventoryPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<InventoryWindow>()
+            .add_system(toggle_inventory_window)
+            .add_system(inventory_window)
+            .add_system(save_and_load_inventory);
+    }
+}
+
+#[derive(Inspectable, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ItemKind {
+    #[default]
+    Ore,
+    EnergyCell,
+    Medkit,
+}
+
+impl ItemKind {
+    pub fn max_stack(&self) -> u32 {
+        match self {
+            ItemKind::Ore => 50,
+            ItemKind::EnergyCell | ItemKind::Medkit => 5,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ItemKind::Ore => "Ore",
+            ItemKind::EnergyCell => "Energy Cell",
+            ItemKind::Medkit => "Medkit",
+        }
+    }
+
+    /// Applies the item's effect, returning false if it can't be used or would be wasted.
+    pub fn use_on(&self, energy: &mut Energy, health: &mut Health) -> bool {
+        match self {
+            ItemKind::Ore => false,
+            ItemKind::EnergyCell if energy.current < energy.max => {
+                energy.current = (energy.current + ENERGY_CELL_CHARGE).min(energy.max);
+                true
+            }
+            ItemKind::Medkit if health.current < health.max => {
+                health.current = (health.current + MEDKIT_HEAL).min(health.max);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Inspectable, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ItemStack {
+    pub kind: ItemKind,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InventoryError {
+    /// Not enough free space; nothing was added.
+    Full {
+        kind: ItemKind,
+        requested: u32,
+        fits: u32,
+    },
+    /// Not enough items to take; nothing was removed.
+    NotEnough {
+        kind: ItemKind,
+        requested: u32,
+        available: u32,
+    },
+    /// A loaded slot holds more than its kind stacks to, or an empty stack.
+    BadStack { slot: usize, stack: ItemStack },
+    /// A loaded inventory has a different number of slots than expected.
+    WrongCapacity { expected: usize, found: usize },
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InventoryError::Full {
+                kind,
+                requested,
+                fits,
+            } => write!(
+                f,
+                "no room for {} {}, only {} fit",
+                requested,
+                kind.name(),
+                fits
+            ),
+            InventoryError::NotEnough {
+                kind,
+                requested,
+                available,
+            } => write!(
+                f,
+                "need {} {} but only have {}",
+                requested,
+                kind.name(),
+                available
+            ),
+            InventoryError::BadStack { slot, stack } => write!(
+                f,
+                "slot {} holds {} {}, but they stack from 1 to {}",
+                slot,
+                stack.count,
+                stack.kind.name(),
+                stack.kind.max_stack()
+            ),
+            InventoryError::WrongCapacity { expected, found } => {
+                write!(f, "expected {} slots but found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for InventoryError {}
+
+/// Why a saved inventory couldn't be loaded.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The save isn't a valid inventory at all.
+    Parse(ron::Error),
+    /// The save parsed, but holds an inventory that can't exist.
+    Invalid(InventoryError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Parse(e) => write!(f, "couldn't parse inventory: {}", e),
+            LoadError::Invalid(e) => write!(f, "invalid inventory: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Parse(e) => Some(e),
+            LoadError::Invalid(e) => Some(e),
+        }
+    }
+}
+
+impl From<ron::Error> for LoadError {
+    fn from(e: ron::Error) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+impl From<InventoryError> for LoadError {
+    fn from(e: InventoryError) -> Self {
+        LoadError::Invalid(e)
+    }
+}
+
+/// A fixed number of slots, each holding a stack of a single item kind.
+#[derive(Component, Inspectable, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Inventory {
+    slots: Vec<Option<ItemStack>>,
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Inventory::with_capacity(12)
+    }
+}
+
+impl Inventory {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Inventory {
+            slots: vec![None; capacity],
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn slots(&self) -> &[Option<ItemStack>] {
+        &self.slots
+    }
+
+    pub fn count(&self, kind: ItemKind) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|stack| stack.kind == kind)
+            .map(|stack| stack.count)
+            .sum()
+    }
+
+    /// How many of `kind` could still be added.
+    pub fn room_for(&self, kind: ItemKind) -> u32 {
+        self.slots
+            .iter()
+            .map(|slot| match slot {
+                None => kind.max_stack(),
+                Some(stack) if stack.kind == kind => kind.max_stack().saturating_sub(stack.count),
+                Some(_) => 0,
+            })
+            .sum()
+    }
+
+    /// Adds all `count` items, topping up existing stacks before starting new ones.
+    pub fn add(&mut self, kind: ItemKind, count: u32) -> Result<(), InventoryError> {
+        let fits = self.room_for(kind);
+        if fits < count {
+            return Err(InventoryError::Full {
+                kind,
+                requested: count,
+                fits,
+            });
+        }
+
+        let mut remaining = count;
+        for stack in self.slots.iter_mut().flatten() {
+            if stack.kind == kind {
+                let added = remaining.min(kind.max_stack().saturating_sub(stack.count));
+                stack.count += added;
+                remaining -= added;
+            }
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if remaining == 0 {
+                break;
+            }
+            let added = remaining.min(kind.max_stack());
+            *slot = Some(ItemStack { kind, count: added });
+            remaining -= added;
+        }
+        Ok(())
+    }
+
+    /// Removes exactly `count` items, taking from the last stacks first.
+    pub fn remove(&mut self, kind: ItemKind, count: u32) -> Result<(), InventoryError> {
+        let available = self.count(kind);
+        if available < count {
+            return Err(InventoryError::NotEnough {
+                kind,
+                requested: count,
+                available,
+            });
+        }
+
+        let mut remaining = count;
+        for slot in self.slots.iter_mut().rev() {
+            if let Some(stack) = slot.filter(|stack| stack.kind == kind) {
+                let taken = remaining.min(stack.count);
+                remaining -= taken;
+                *slot = (stack.count > taken).then_some(ItemStack {
+                    kind,
+                    count: stack.count - taken,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves exactly `count` items into `other`. On an error neither inventory changes.
+    pub fn transfer(
+        &mut self,
+        other: &mut Inventory,
+        kind: ItemKind,
+        count: u32,
+    ) -> Result<(), InventoryError> {
+        let available = self.count(kind);
+        if available < count {
+            return Err(InventoryError::NotEnough {
+                kind,
+                requested: count,
+                available,
+            });
+        }
+        let fits = other.room_for(kind);
+        if fits < count {
+            return Err(InventoryError::Full {
+                kind,
+                requested: count,
+                fits,
+            });
+        }
+        self.remove(kind, count)?;
+        other.add(kind, count)
+    }
+
+    pub fn to_ron(&self) -> Result<String, ron::Error> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+    }
+
+    /// Parses a saved inventory, rejecting one that doesn't have `capacity` slots or
+    /// holds stacks no amount of playing could have produced.
+    pub fn from_ron(source: &str, capacity: usize) -> Result<Self, LoadError> {
+        let inventory: Inventory = ron::from_str(source)?;
+        if inventory.capacity() != capacity {
+            return Err(InventoryError::WrongCapacity {
+                expected: capacity,
+                found: inventory.capacity(),
+            }
+            .into());
+        }
+        for (slot, stack) in inventory.slots.iter().enumerate() {
+            if let Some(&stack) = stack.as_ref() {
+                if stack.count == 0 || stack.count > stack.kind.max_stack() {
+                    return Err(InventoryError::BadStack { slot, stack }.into());
+                }
+            }
+        }
+        Ok(inventory)
+    }
+}
+
+/// Which players have their inventory open, by player index.
+#[derive(Default)]
+pub struct InventoryWindow {
+    pub open: [bool; MAX_PLAYERS],
+}
+
+/// I opens the keyboard player's inventory, and select a gamepad player's.
+fn toggle_inventory_window(
+    mut window: ResMut<InventoryWindow>,
+    keyboard: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    players: Query<(&Player, &Controls)>,
+) {
+    for (player, &controls) in players.iter() {
+        let pressed = match controls {
+            Controls::KeyboardMouse => keyboard.just_pressed(KeyCode::I),
+            Controls::Gamepad(gamepad) => {
+                buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::Select))
+            }
+        };
+        if pressed {
+            let open = &mut window.open[player.index];
+            *open = !*open;
+        }
+    }
+}
+
+/// Each player's inventory opens in the top right of their view. Clicking a stack
+/// uses one of its items, and right-clicking gives one to the next player.
+fn inventory_window(
+    mut egui_context: ResMut<EguiContext>,
+    mut window: ResMut<InventoryWindow>,
+    mut inventories: Query<(&Player, &mut Inventory, &mut Energy, &mut Health)>,
+    views: Query<&PlayerView>,
+    windows: Res<Windows>,
+) {
+    let screen = windows.get_primary().unwrap();
+    let screen_size = Vec2::new(screen.width(), screen.height());
+    let mut given = None;
+    for view in views.iter() {
+        let (player, mut inventory, mut energy, mut health) = match inventories.get_mut(view.player)
+        {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        // egui measures from the top left, the viewport from the bottom left.
+        let offset = screen_size - view.viewport.max() + Vec2::splat(10.0);
+
+        egui::Window::new(format!("Player {} Inventory", player.index + 1))
+            .id(egui::Id::new(("inventory", player.index)))
+            .open(&mut window.open[player.index])
+            .anchor(egui::Align2::RIGHT_TOP, [-offset.x, offset.y])
+            .resizable(false)
+            .show(egui_context.ctx_mut(), |ui| {
+                let mut used = None;
+                egui::Grid::new(("inventory_slots", player.index)).show(ui, |ui| {
+                    for (i, slot) in inventory.slots().iter().enumerate() {
+                        let text = match slot {
+                            Some(stack) => format!("{}\n{}", stack.kind.name(), stack.count),
+                            None => "\n".to_string(),
+                        };
+                        let button = ui.add_sized([72.0, 40.0], egui::Button::new(text));
+                        if button.clicked() {
+                            used = slot.map(|stack| stack.kind);
+                        }
+                        if button.secondary_clicked() {
+                            given = slot.map(|stack| (view.player, stack.kind));
+                        }
+                        if i % 4 == 3 {
+                            ui.end_row();
+                        }
+                    }
+                });
+                if let Some(kind) = used {
+                    if inventory.count(kind) > 0 && kind.use_on(&mut energy, &mut health) {
+                        inventory.remove(kind, 1).unwrap();
+                    }
+                }
+            });
+    }
+
+    let (giver, kind) = match given {
+        Some(given) => given,
+        None => return,
+    };
+    let mut players: Vec<_> = views.iter().map(|view| (view.index, view.player)).collect();
+    players.sort_unstable();
+    let next = match players.iter().position(|&(_, player)| player == giver) {
+        Some(i) => players[(i + 1) % players.len()].1,
+        None => return,
+    };
+    if next == giver {
+        return;
+    }
+    // Both inventories can't be borrowed from the query at once.
+    let mut receiver = match inventories.get(next) {
+        Ok((_, inventory, ..)) => inventory.clone(),
+        Err(_) => return,
+    };
+    let result = match inventories.get_mut(giver) {
+        Ok((_, mut inventory, ..)) => inventory.transfer(&mut receiver, kind, 1),
+        Err(_) => return,
+    };
+    match result {
+        Ok(()) => *inventories.get_mut(next).unwrap().1 = receiver,
+        Err(e) => info!("Couldn't hand over {}: {}", kind.name(), e),
+    }
+}
+
+fn save_path(player: &Player) -> String {
+    match player.index {
+        0 => INVENTORY_SAVE.to_string(),
+        index => format!("{}/inventory_{}.ron", SAVE_DIR, index + 1),
+    }
+}
+
+/// F5 saves everyone's inventory, each to their own file, and F9 loads them back.
+fn save_and_load_inventory(
+    mut inventories: Query<(&Player, &mut Inventory)>,
+    keyboard: Res<Input<KeyCode>>,
+) {
+    for (player, mut inventory) in inventories.iter_mut() {
+        let path = save_path(player);
+
+        if keyboard.just_pressed(KeyCode::F5) {
+            let result = inventory
+                .to_ron()
+                .map_err(|e| e.to_string())
+                .and_then(|ron| {
+                    fs::create_dir_all(SAVE_DIR)
+                        .and_then(|_| fs::write(&path, ron))
+                        .map_err(|e| e.to_string())
+                });
+            match result {
+                Ok(()) => info!("Saved inventory to {}", path),
+                Err(e) => error!("Failed to save inventory: {}", e),
+            }
+        }
+
+        if keyboard.just_pressed(KeyCode::F9) && Path::new(&path).exists() {
+            let result = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|ron| {
+                    Inventory::from_ron(&ron, inventory.capacity()).map_err(|e| e.to_string())
+                });
+            match result {
+                Ok(loaded) => *inventory = loaded,
+                Err(e) => error!("Failed to load inventory: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_tops_up_stacks_before_filling_slots() {
+        let mut inventory = Inventory::with_capacity(3);
+        inventory.add(ItemKind::Ore, 40).unwrap();
+        inventory.add(ItemKind::Ore, 20).unwrap();
+        assert_eq!(
+            inventory.slots(),
+            &[
+                Some(ItemStack {
+                    kind: ItemKind::Ore,
+                    count: 50
+                }),
+                Some(ItemStack {
+                    kind: ItemKind::Ore,
+                    count: 10
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn add_and_remove_are_all_or_nothing() {
+        let mut inventory = Inventory::with_capacity(1);
+        inventory.add(ItemKind::Medkit, 4).unwrap();
+        assert!(inventory.add(ItemKind::Medkit, 2).is_err());
+        assert!(inventory.remove(ItemKind::Medkit, 5).is_err());
+        assert_eq!(inventory.count(ItemKind::Medkit), 4);
+        inventory.remove(ItemKind::Medkit, 4).unwrap();
+        assert_eq!(inventory.slots(), &[None]);
+    }
+
+    #[test]
+    fn transfer_moves_items_or_nothing() {
+        let mut from = Inventory::with_capacity(2);
+        let mut to = Inventory::with_capacity(1);
+        from.add(ItemKind::Medkit, 4).unwrap();
+        from.add(ItemKind::Ore, 1).unwrap();
+
+        from.transfer(&mut to, ItemKind::Medkit, 3).unwrap();
+        assert_eq!(from.count(ItemKind::Medkit), 1);
+        assert_eq!(to.count(ItemKind::Medkit), 3);
+
+        assert_eq!(
+            from.transfer(&mut to, ItemKind::Medkit, 2),
+            Err(InventoryError::NotEnough {
+                kind: ItemKind::Medkit,
+                requested: 2,
+                available: 1,
+            })
+        );
+        // The only slot already holds medkits, so ore has nowhere to go.
+        assert_eq!(
+            from.transfer(&mut to, ItemKind::Ore, 1),
+            Err(InventoryError::Full {
+                kind: ItemKind::Ore,
+                requested: 1,
+                fits: 0,
+            })
+        );
+        assert_eq!(from.count(ItemKind::Medkit), 1);
+        assert_eq!(from.count(ItemKind::Ore), 1);
+        assert_eq!(to.count(ItemKind::Medkit), 3);
+        assert_eq!(to.count(ItemKind::Ore), 0);
+    }
+
+    #[test]
+    fn room_for_ignores_overfull_stacks() {
+        let inventory = Inventory {
+            slots: vec![
+                Some(ItemStack {
+                    kind: ItemKind::Medkit,
+                    count: 9,
+                }),
+                None,
+            ],
+        };
+        assert_eq!(inventory.room_for(ItemKind::Medkit), 5);
+    }
+
+    #[test]
+    fn from_ron_round_trips() {
+        let mut inventory = Inventory::with_capacity(4);
+        inventory.add(ItemKind::Ore, 7).unwrap();
+        inventory.add(ItemKind::EnergyCell, 2).unwrap();
+        let ron = inventory.to_ron().unwrap();
+        assert_eq!(Inventory::from_ron(&ron, 4).unwrap(), inventory);
+    }
+
+    #[test]
+    fn from_ron_rejects_wrong_capacity() {
+        let ron = Inventory::with_capacity(4).to_ron().unwrap();
+        assert!(matches!(
+            Inventory::from_ron(&ron, 12),
+            Err(LoadError::Invalid(InventoryError::WrongCapacity {
+                expected: 12,
+                found: 4,
+            }))
+        ));
+    }
+
+    #[test]
+    fn from_ron_rejects_bad_stacks() {
+        let overfull = "(slots: [Some((kind: Medkit, count: 6)), None])";
+        assert!(matches!(
+            Inventory::from_ron(overfull, 2),
+            Err(LoadError::Invalid(InventoryError::BadStack { slot: 0, .. }))
+        ));
+        let empty = "(slots: [Some((kind: Ore, count: 0)), None])";
+        assert!(matches!(
+            Inventory::from_ron(empty, 2),
+            Err(LoadError::Invalid(InventoryError::BadStack { slot: 0, .. }))
+        ));
+        assert!(matches!(
+            Inventory::from_ron("(slots: [Some((kind: Crystal", 2),
+            Err(LoadError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn items_are_only_used_when_they_help() {
+        let mut energy = Energy::default();
+        let mut health = Health::default();
+        assert!(!ItemKind::Medkit.use_on(&mut energy, &mut health));
+        assert!(!ItemKind::EnergyCell.use_on(&mut energy, &mut health));
+
+        health.current = 1.0;
+        energy.current = 90.0;
+        assert!(ItemKind::Medkit.use_on(&mut energy, &mut health));
+        assert!(ItemKind::EnergyCell.use_on(&mut energy, &mut health));
+        assert_eq!(health.current, 1.0 + MEDKIT_HEAL);
+        assert_eq!(energy.current, energy.max);
+        assert!(!ItemKind::Ore.use_on(&mut energy, &mut health));
+    }
+}
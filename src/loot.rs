@@ -0,0 +1,285 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_inspector_egui::Inspectable;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+use crate::collision::{Collider, CollisionEvent, CollisionsDetected, Layers, SpatialGrid};
+use crate::inventory::{Inventory, ItemKind};
+use crate::player::{Health, Player};
+use crate::rock::{RockDestroyed, RockSize, RocksDamaged, Velocity};
+use crate::weapon::Energy;
+
+const LOOT_TABLES: &str = include_str!("../assets/loot_tables.ron");
+
+const PICKUP_RADIUS: f32 = 0.1;
+const MAGNET_RADIUS: f32 = 1.5;
+const MAGNET_ACCELERATION: f32 = 20.0;
+/// How quickly pickups slow down when nothing is pulling them, per second.
+const PICKUP_DRAG: f32 = 3.0;
+
+pub struct LootPlugin;
+
+impl Plugin for LootPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LootRng>()
+            .insert_resource(LootTables::from_ron(LOOT_TABLES))
+            .add_system(attract_pickups)
+            .add_system_to_stage(CoreStage::PostUpdate, drop_loot.after(RocksDamaged))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                collect_pickups.after(CollisionsDetected),
+            );
+    }
+}
+
+/// The random source for loot rolls and rock fragments. Set `LOOT_SEED` to make drops
+/// reproducible.
+#[derive(Deref, DerefMut)]
+pub struct LootRng(StdRng);
+
+impl Default for LootRng {
+    fn default() -> Self {
+        match std::env::var("LOOT_SEED").ok().and_then(|s| s.parse().ok()) {
+            Some(seed) => LootRng::seeded(seed),
+            None => LootRng(StdRng::from_entropy()),
+        }
+    }
+}
+
+impl LootRng {
+    pub fn seeded(seed: u64) -> Self {
+        LootRng(StdRng::seed_from_u64(seed))
+    }
+}
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Loot {
+    Ore,
+    Energy,
+    Health,
+}
+
+impl Loot {
+    fn color(&self) -> Color {
+        match self {
+            Loot::Ore => Color::ORANGE,
+            Loot::Energy => Color::CYAN,
+            Loot::Health => Color::LIME_GREEN,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LootEntry {
+    pub loot: Loot,
+    pub weight: u32,
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LootTable {
+    /// How many times the table is rolled per drop.
+    pub rolls: u32,
+    /// Weight of rolling nothing at all.
+    pub nothing: u32,
+    pub entries: Vec<LootEntry>,
+}
+
+impl LootTable {
+    pub fn roll(&self, rng: &mut impl Rng) -> Vec<(Loot, u32)> {
+        let total = self.nothing + self.entries.iter().map(|e| e.weight).sum::<u32>();
+        if total == 0 {
+            return Vec::new();
+        }
+
+        let mut drops = Vec::new();
+        for _ in 0..self.rolls {
+            let mut pick = rng.gen_range(0..total);
+            if pick < self.nothing {
+                continue;
+            }
+            pick -= self.nothing;
+            for entry in &self.entries {
+                if pick < entry.weight {
+                    drops.push((entry.loot, rng.gen_range(entry.min..=entry.max)));
+                    break;
+                }
+                pick -= entry.weight;
+            }
+        }
+        drops
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LootTables {
+    pub tables: HashMap<RockSize, LootTable>,
+}
+
+impl LootTables {
+    pub fn from_ron(source: &str) -> Self {
+        ron::from_str(source).expect("invalid loot tables")
+    }
+}
+
+#[derive(Component, Inspectable)]
+pub struct Pickup {
+    pub loot: Loot,
+    pub amount: u32,
+}
+
+fn drop_loot(
+    mut commands: Commands,
+    mut destroyed: EventReader<RockDestroyed>,
+    tables: Res<LootTables>,
+    mut rng: ResMut<LootRng>,
+) {
+    for rock in destroyed.iter() {
+        let table = match tables.tables.get(&rock.size) {
+            Some(table) => table,
+            None => continue,
+        };
+        for (loot, amount) in table.roll(&mut rng.0) {
+            let angle = rng.0.gen_range(0.0..TAU);
+            let speed = rng.0.gen_range(0.5..2.0);
+            commands
+                .spawn_bundle(SpriteBundle {
+                    transform: Transform::from_translation(rock.position.extend(0.2)),
+                    sprite: Sprite {
+                        color: loot.color(),
+                        custom_size: Some(Vec2::splat(0.15)),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(Pickup { loot, amount })
+                .insert(Velocity(
+                    rock.velocity + speed * Vec2::new(angle.cos(), angle.sin()),
+                ))
+                .insert(Collider {
+                    radius: PICKUP_RADIUS,
+                    layers: Layers::PICKUP,
+                    mask: Layers::empty(),
+                })
+                .insert(Name::new("Pickup"));
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn attract_pickups(
+    grid: Res<SpatialGrid>,
+    players: Query<&Transform, With<Player>>,
+    mut pickups: Query<(&mut Velocity, &mut Transform), (With<Pickup>, Without<Player>)>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_seconds();
+    let drag = (-PICKUP_DRAG * dt).exp();
+    for (mut velocity, mut transform) in pickups.iter_mut() {
+        **velocity *= drag;
+        transform.translation += velocity.extend(0.0) * dt;
+    }
+
+    for player_tf in players.iter() {
+        let center = player_tf.translation.xy();
+        for entity in grid.query_circle(center, MAGNET_RADIUS, Layers::PICKUP) {
+            if let Ok((mut velocity, transform)) = pickups.get_mut(entity) {
+                let pull = (center - transform.translation.xy()).normalize_or_zero();
+                **velocity += pull * MAGNET_ACCELERATION * dt;
+            }
+        }
+    }
+}
+
+fn collect_pickups(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    mut players: Query<(&mut Inventory, &mut Energy, &mut Health), With<Player>>,
+    pickups: Query<&Pickup>,
+) {
+    let mut collected = HashSet::new();
+    for collision in collisions.iter() {
+        let (mut inventory, mut energy, mut health) = match players.get_mut(collision.entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let pickup = match pickups.get(collision.other) {
+            Ok(pickup) => pickup,
+            Err(_) => continue,
+        };
+        if collected.contains(&collision.other) {
+            continue;
+        }
+
+        match pickup.loot {
+            Loot::Ore => {
+                // Leave the pickup lying around if there's no room for it.
+                if inventory.add(ItemKind::Ore, pickup.amount).is_err() {
+                    continue;
+                }
+            }
+            // Anything that would go to waste is kept for later, if there's room.
+            Loot::Energy if energy.current >= energy.max => {
+                if inventory.add(ItemKind::EnergyCell, 1).is_err() {
+                    continue;
+                }
+            }
+            Loot::Energy => {
+                energy.current = (energy.current + pickup.amount as f32).min(energy.max);
+            }
+            Loot::Health if health.current >= health.max => {
+                if inventory.add(ItemKind::Medkit, 1).is_err() {
+                    continue;
+                }
+            }
+            Loot::Health => {
+                health.current = (health.current + pickup.amount as f32).min(health.max);
+            }
+        }
+        collected.insert(collision.other);
+        commands.entity(collision.other).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roll_all(seed: u64) -> Vec<(RockSize, Vec<(Loot, u32)>)> {
+        let tables = LootTables::from_ron(LOOT_TABLES);
+        let mut rng = LootRng::seeded(seed);
+        [RockSize::Small, RockSize::Medium, RockSize::Large]
+            .into_iter()
+            .flat_map(|size| std::iter::repeat_n(size, 3))
+            .map(|size| (size, tables.tables[&size].roll(&mut *rng)))
+            .collect()
+    }
+
+    #[test]
+    fn seeded_rolls_are_reproducible() {
+        assert_eq!(roll_all(7), roll_all(7));
+    }
+
+    #[test]
+    fn seeded_rolls_give_exact_drops() {
+        use Loot::*;
+        use RockSize::*;
+        let expected = vec![
+            (Small, vec![]),
+            (Small, vec![]),
+            (Small, vec![]),
+            (Medium, vec![(Energy, 17), (Ore, 1)]),
+            (Medium, vec![(Ore, 2)]),
+            (Medium, vec![(Energy, 11), (Ore, 1)]),
+            (Large, vec![(Energy, 23), (Energy, 17), (Energy, 23)]),
+            (Large, vec![(Energy, 18), (Ore, 2)]),
+            (Large, vec![(Ore, 3), (Ore, 4)]),
+        ];
+        assert_eq!(roll_all(42), expected);
+    }
+}
@@ -1,38 +1,100 @@
 use bevy::prelude::*;
+use bevy::window::WindowMode;
 
-use bevy_inspector_egui::{RegisterInspectable, WorldInspectorPlugin};
+use bevy_inspector_egui::{InspectorPlugin, RegisterInspectable, WorldInspectorPlugin};
 use player::PlayerPlugin;
 
-use self::camera_controller::CameraControllerPlugin;
+use self::ai::{Ai, AiPlugin};
+use self::camera_controller::{CameraControllerPlugin, CameraSettings, FreeCamera};
+use self::camera_effects::{CameraEffectsPlugin, ShakeSettings};
+use self::collision::CollisionPlugin;
 use self::cursor::CursorPlugin;
 use self::debug::DebugPlugin;
-use self::player::Player;
+use self::director::{DirectorPlugin, WaveState};
+use self::enemy::{Enemy, EnemyPlugin, Gun};
+use self::inventory::{Inventory, InventoryPlugin};
+use self::loot::{LootPlugin, Pickup};
+use self::player::{Health, Knockback, Player, PlayerInput};
+use self::rock::{AngularVelocity, Rock, RockPlugin, Velocity};
+use self::selection::SelectionPlugin;
+use self::spawner::{Spawner, SpawnerPlugin};
+use self::split_screen::SplitScreenPlugin;
+use self::steering::{Steering, SteeringPlugin};
 use self::tilemap::{TileMapPlugin, TileKind};
+use self::weapon::{Arsenal, Energy, WeaponPlugin};
 
+mod ai;
 mod camera_controller;
+mod camera_effects;
+mod collision;
 mod cursor;
 mod debug;
+mod director;
+mod enemy;
+mod inventory;
+mod loot;
+mod navigation;
 mod player;
+mod raycast;
+mod rock;
+mod selection;
+mod spawner;
+mod split_screen;
+mod steering;
 mod tilemap;
+mod weapon;
 
 fn main() {
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.35, 0.1, 0.7)))
         .insert_resource(WindowDescriptor {
             title: "Making a Game in Rust".to_string(),
-            resizable: false,
+            resizable: true,
             ..Default::default()
         })
         .add_plugins(DefaultPlugins)
         .add_plugin(WorldInspectorPlugin::new())
         .register_inspectable::<Player>()
+        .register_inspectable::<PlayerInput>()
+        .register_inspectable::<Knockback>()
+        .register_inspectable::<Health>()
+        .register_inspectable::<Inventory>()
+        .register_inspectable::<Pickup>()
+        .add_plugin(InspectorPlugin::<WaveState>::new())
+        .add_plugin(InspectorPlugin::<CameraSettings>::new())
+        .add_plugin(InspectorPlugin::<FreeCamera>::new())
+        .add_plugin(InspectorPlugin::<ShakeSettings>::new())
         .register_inspectable::<TileKind>()
+        .register_inspectable::<Arsenal>()
+        .register_inspectable::<Energy>()
+        .register_inspectable::<Rock>()
+        .register_inspectable::<Velocity>()
+        .register_inspectable::<AngularVelocity>()
+        .register_inspectable::<Enemy>()
+        .register_inspectable::<Gun>()
+        .register_inspectable::<Steering>()
+        .register_inspectable::<Ai>()
+        .register_inspectable::<Spawner>()
+        .add_plugin(SplitScreenPlugin)
         .add_plugin(CameraControllerPlugin)
+        .add_plugin(CameraEffectsPlugin)
         .add_plugin(CursorPlugin)
         .add_plugin(TileMapPlugin)
+        .add_plugin(CollisionPlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(WeaponPlugin)
+        .add_plugin(RockPlugin)
+        .add_plugin(SteeringPlugin)
+        .add_plugin(AiPlugin)
+        .add_plugin(EnemyPlugin)
+        .add_plugin(SpawnerPlugin)
+        .add_plugin(LootPlugin)
+        .add_plugin(InventoryPlugin)
+        .add_plugin(DirectorPlugin)
+        .add_plugin(SelectionPlugin)
         .add_plugin(DebugPlugin)
         .add_startup_system(setup)
+        .add_system(toggle_fullscreen)
         .run();
 }
 
@@ -46,3 +108,14 @@ fn setup(mut windows: ResMut<Windows>) {
 
     window.set_cursor_visibility(false);
 }
+
+fn toggle_fullscreen(mut windows: ResMut<Windows>, keyboard: Res<Input<KeyCode>>) {
+    if !keyboard.just_pressed(KeyCode::F11) {
+        return;
+    }
+    let window = windows.get_primary_mut().unwrap();
+    window.set_mode(match window.mode() {
+        WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+        _ => WindowMode::Windowed,
+    });
+}
@@ -0,0 +1,147 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::raycast::{ray_grid, tile_at};
+use crate::tilemap::TileMap;
+
+/// Searches give up after expanding this many tiles.
+const MAX_EXPANDED: usize = 4096;
+
+const NEIGHBOURS: [(i32, i32); 8] = [
+    (1, 0),
+    (-1, 0),
+    (0, 1),
+    (0, -1),
+    (1, 1),
+    (1, -1),
+    (-1, 1),
+    (-1, -1),
+];
+
+/// Whether the straight line between two points crosses no solid tiles.
+pub fn line_of_sight(tilemap: &TileMap, from: Vec2, to: Vec2) -> bool {
+    let offset = to - from;
+    let distance = offset.length();
+    if distance <= f32::EPSILON {
+        return !tilemap.is_solid(tile_at(from));
+    }
+    ray_grid(from, offset / distance, distance, |tile| {
+        tilemap.is_solid(tile)
+    })
+    .is_none()
+}
+
+fn walkable(tilemap: &TileMap, tile: IVec2) -> bool {
+    tilemap
+        .get_tile(tile)
+        .is_some_and(|kind| kind.is_walkable())
+}
+
+/// Octile distance, the exact cost of a path with no obstacles in the way.
+fn heuristic(a: IVec2, b: IVec2) -> f32 {
+    let d = (a - b).abs();
+    let (short, long) = (d.x.min(d.y) as f32, d.x.max(d.y) as f32);
+    long + (std::f32::consts::SQRT_2 - 1.0) * short
+}
+
+#[derive(PartialEq)]
+struct Open {
+    cost: f32,
+    tile: IVec2,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    // Reversed so the heap pops the cheapest tile, with ties broken by position
+    // so that searches are deterministic.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| (other.tile.x, other.tile.y).cmp(&(self.tile.x, self.tile.y)))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A* over walkable tiles, moving in eight directions without cutting corners.
+/// Returns the tiles to visit after `start`, ending with `goal`.
+pub fn find_path(tilemap: &TileMap, start: IVec2, goal: IVec2) -> Option<Vec<IVec2>> {
+    if !walkable(tilemap, goal) {
+        return None;
+    }
+    if start == goal {
+        return Some(Vec::new());
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from = HashMap::default();
+    let mut cost_so_far = HashMap::default();
+    open.push(Open {
+        cost: heuristic(start, goal),
+        tile: start,
+    });
+    cost_so_far.insert(start, 0.0);
+
+    let mut expanded = 0;
+    while let Some(Open { tile, .. }) = open.pop() {
+        if tile == goal {
+            let mut path = vec![goal];
+            let mut current = goal;
+            while let Some(&previous) = came_from.get(&current) {
+                if previous == start {
+                    break;
+                }
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        expanded += 1;
+        if expanded > MAX_EXPANDED {
+            return None;
+        }
+
+        let cost = cost_so_far[&tile];
+        for (dx, dy) in NEIGHBOURS {
+            let step = IVec2::new(dx, dy);
+            let next = tile + step;
+            if !walkable(tilemap, next) {
+                continue;
+            }
+            let diagonal = step.x != 0 && step.y != 0;
+            if diagonal
+                && (!walkable(tilemap, tile + IVec2::new(step.x, 0))
+                    || !walkable(tilemap, tile + IVec2::new(0, step.y)))
+            {
+                continue;
+            }
+
+            let next_cost = cost
+                + if diagonal {
+                    std::f32::consts::SQRT_2
+                } else {
+                    1.0
+                };
+            if cost_so_far.get(&next).is_some_and(|&c| c <= next_cost) {
+                continue;
+            }
+            cost_so_far.insert(next, next_cost);
+            came_from.insert(next, tile);
+            open.push(Open {
+                cost: next_cost + heuristic(next, goal),
+                tile: next,
+            });
+        }
+    }
+    None
+}
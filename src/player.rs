@@ -1,32 +1,93 @@
-use crate::cursor::{Cursor, CursorState, MousePos};
-use crate::debug::{DebugCircle, DebugRect};
+use crate::camera_controller::FreeCamera;
+use crate::camera_effects::{CameraEffect, CameraEffectKind, CameraEffects, CameraShake};
+use crate::collision::{Collider, CollisionEvent, CollisionsDetected, Layers};
+use crate::cursor::{spawn_reticle, Cursor, CursorState, MousePos};
+use crate::debug::DebugCircle;
+use crate::inventory::Inventory;
+use crate::rock::{Rock, Velocity};
+use crate::weapon::{facing, Arsenal, Energy, Hit, HitsResolved};
 use bevy::math::Vec3Swizzles;
 use bevy::prelude::*;
 use bevy_inspector_egui::bevy_egui::EguiContext;
 use bevy_inspector_egui::Inspectable;
 use std::f32::consts::PI;
-use std::time::Duration;
 
 const COMPASS_SPRITE: &str = "compass.png";
-const LASER_SPRITE: &str = "laser.png";
-const ROCK_SPRITE: &str = "rock.png";
+
+const PLAYER_RADIUS: f32 = 0.25;
+const KNOCKBACK_SPEED: f32 = 3.0;
+/// How quickly knockback dies off, per second.
+const KNOCKBACK_DAMPING: f32 = 6.0;
+
+pub const MAX_PLAYERS: usize = 4;
+/// How far in front of a gamepad player their reticle sits.
+const GAMEPAD_AIM_DISTANCE: f32 = 3.0;
+/// Right stick deflection below this leaves the aim where it was.
+const GAMEPAD_AIM_DEAD_ZONE: f32 = 0.3;
+/// New players join this far from the first one.
+const JOIN_OFFSET: f32 = 1.0;
+
+const PLAYER_COLORS: [Color; MAX_PLAYERS] = [Color::WHITE, Color::CYAN, Color::YELLOW, Color::PINK];
 
 #[derive(Component, Inspectable)]
 pub struct Player {
     speed: f32,
+    /// 0 for the keyboard and mouse player. Also orders the split-screen views.
+    pub index: usize,
+}
+
+impl Player {
+    pub fn new(index: usize) -> Self {
+        Player { speed: 4.0, index }
+    }
+}
+
+/// The device a player is controlled with.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Controls {
+    KeyboardMouse,
+    Gamepad(Gamepad),
+}
+
+/// This frame's input for a player, read from their [`Controls`].
+#[derive(Component, Inspectable, Clone, Copy, Default)]
+pub struct PlayerInput {
+    /// Per axis, in [-1, 1].
+    pub movement: Vec2,
+    /// The world position the player is aiming at.
+    pub aim: Vec2,
+    pub fire: bool,
+}
+
+#[derive(Component, Inspectable, Clone, Copy, Default, Deref, DerefMut)]
+pub struct Knockback(pub Vec2);
+
+#[derive(Component, Inspectable)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
 }
 
-#[derive(Component)]
-pub struct Laser {
-    lifetime: Duration,
-    origin: Duration,
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            current: 5.0,
+            max: 5.0,
+        }
+    }
 }
 
 pub struct PlayerPlugin;
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct PlayerInputRead;
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
 pub struct PlayerMoved;
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct PlayerDamaged;
+
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_state(CursorState::GameCursor)
@@ -38,80 +99,21 @@ impl Plugin for PlayerPlugin {
                 SystemSet::on_update(CursorState::UICursor).with_system(change_cursor_state),
             )
             .add_system_set(
-                SystemSet::on_update(CursorState::GameCursor)
-                    .with_system(shoot)
-                    .with_system(change_cursor_state),
+                SystemSet::on_update(CursorState::GameCursor).with_system(change_cursor_state),
             )
-            .add_startup_system(spawn_player)
-            .add_startup_system(spawn_some_rocks)
-            .add_system(move_player.label(PlayerMoved))
-            .add_system(spawn_some_rocks_on_space)
-            .add_system(on_hit_rock)
-            .add_system(move_laser);
-    }
-}
-
-#[derive(Component)]
-pub struct Rock;
-
-fn spawn_some_rocks_on_space(
-    commands: Commands,
-    assets: Res<AssetServer>,
-    input: Res<Input<KeyCode>>,
-) {
-    if input.just_pressed(KeyCode::Space) {
-        spawn_some_rocks(commands, assets);
-    }
-}
-
-fn spawn_some_rocks(mut commands: Commands, assets: Res<AssetServer>) {
-    let image = assets.load(ROCK_SPRITE);
-
-    for _ in 0..10 {
-        commands
-            .spawn_bundle(SpriteBundle {
-                transform: Transform {
-                    translation: Vec3::new(
-                        6. * rand::random::<f32>() - 3.,
-                        6. * rand::random::<f32>() - 3.,
-                        0.1,
-                    ),
-                    scale: Vec3::splat(0.25),
-                    ..Default::default()
-                },
-                texture: image.clone(),
-                sprite: Sprite {
-                    custom_size: Some((1.0, 1.0).into()),
-                    ..Default::default()
-                },
-                ..Default::default()
-            })
-            .insert(Rock)
-            .insert(DebugCircle {
-                color: Color::BLUE,
-                radius: 1.0 / 4.0,
-            })
-            .insert(DebugRect {
-                color: Color::GREEN,
-                rotation: 0.0,
-                size: (1.0 / 4.0, 1.0 / 4.0).into(),
-            })
-            .insert(Name::new("Rock"));
-    }
-}
-
-fn on_hit_rock(
-    mut commands: Commands,
-    lasers: Query<(Entity, &Transform), With<Laser>>,
-    rocks: Query<(Entity, &Transform), With<Rock>>,
-) {
-    for (laser_ent, laser_tr) in lasers.iter() {
-        for (rock_ent, rock_tr) in rocks.iter() {
-            if laser_tr.translation.xy().distance(rock_tr.translation.xy()) <= 0.2 {
-                commands.entity(laser_ent).despawn_recursive();
-                commands.entity(rock_ent).despawn_recursive();
-            }
-        }
+            .add_startup_system(spawn_first_player)
+            .add_system(join_players)
+            .add_system(leave_players)
+            .add_system(read_player_input.label(PlayerInputRead))
+            .add_system(move_player.label(PlayerMoved).after(PlayerInputRead))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                knock_back_player.after(CollisionsDetected),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                damage_player.label(PlayerDamaged).after(HitsResolved),
+            );
     }
 }
 
@@ -147,100 +149,243 @@ pub fn to_ui_cursor(
     window.set_cursor_visibility(true);
 }
 
-fn spawn_player(mut commands: Commands, assets: Res<AssetServer>) {
+fn spawn_first_player(mut commands: Commands, assets: Res<AssetServer>) {
+    spawn_player(
+        &mut commands,
+        &assets,
+        0,
+        Controls::KeyboardMouse,
+        Vec2::ZERO,
+    );
+}
+
+pub fn spawn_player(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    index: usize,
+    controls: Controls,
+    position: Vec2,
+) -> Entity {
     let image = assets.load(COMPASS_SPRITE);
+    let color = PLAYER_COLORS[index % MAX_PLAYERS];
 
-    commands
+    let player = commands
         .spawn_bundle(SpriteBundle {
             transform: Transform {
-                translation: Vec3::new(0., 0., 0.9),
+                translation: position.extend(0.9),
                 scale: Vec3::splat(1.0 / 16.0),
                 ..Default::default()
             },
+            sprite: Sprite {
+                color,
+                ..Default::default()
+            },
             texture: image,
             ..Default::default()
         })
-        .insert(Player { speed: 4.0 })
-        .insert(Name::new("Player"))
+        .insert(Player::new(index))
+        .insert(controls)
+        .insert(PlayerInput {
+            aim: position + Vec2::Y,
+            ..Default::default()
+        })
+        .insert(Knockback::default())
+        .insert(Health::default())
+        .insert(Inventory::default())
+        .insert(Collider {
+            radius: PLAYER_RADIUS,
+            layers: Layers::PLAYER,
+            mask: Layers::TERRAIN | Layers::PICKUP,
+        })
+        .insert(Arsenal::default())
+        .insert(Energy::default())
+        .insert(Name::new(format!("Player {}", index + 1)))
         .insert(DebugCircle {
             color: Color::GREEN,
             radius: 0.5,
-        });
+        })
+        .id();
+
+    // The keyboard player already has the mouse cursor.
+    if controls != Controls::KeyboardMouse {
+        spawn_reticle(commands, assets, player, color);
+    }
+    player
 }
 
-fn shoot(
+/// Pressing start on a gamepad that isn't in the game yet adds a player for it.
+fn join_players(
     mut commands: Commands,
     assets: Res<AssetServer>,
-    query: Query<&Transform, With<Player>>,
-    mouse: Res<Input<MouseButton>>,
-    time: Res<Time>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<Input<GamepadButton>>,
+    players: Query<(&Player, &Controls, &Transform)>,
 ) {
-    if mouse.just_pressed(MouseButton::Left) {
-        let transform = query.single();
-
-        let laser_image = assets.load(LASER_SPRITE);
-
-        commands
-            .spawn_bundle(SpriteBundle {
-                transform: Transform {
-                    translation: transform.translation.xy().extend(0.),
-                    rotation: transform.rotation,
-                    scale: Vec3::splat(1.0 / 64.0),
-                },
-                texture: laser_image,
-                ..Default::default()
-            })
-            .insert(Laser {
-                lifetime: Duration::from_secs(1),
-                origin: time.time_since_startup(),
-            })
-            .insert(Name::new("Player Laser"))
-            .insert(DebugCircle {
-                color: Color::BLUE,
-                radius: 0.2,
-            });
+    for &gamepad in gamepads.iter() {
+        if !buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::Start)) {
+            continue;
+        }
+        if players
+            .iter()
+            .any(|(_, &controls, _)| controls == Controls::Gamepad(gamepad))
+        {
+            continue;
+        }
+        let index = match (0..MAX_PLAYERS).find(|&i| players.iter().all(|(p, _, _)| p.index != i)) {
+            Some(index) => index,
+            None => return,
+        };
+        let first = players
+            .iter()
+            .min_by_key(|(player, _, _)| player.index)
+            .map_or(Vec2::ZERO, |(_, _, tf)| tf.translation.xy());
+        let angle = index as f32 * PI / 2.0;
+        let position = first + JOIN_OFFSET * Vec2::new(angle.cos(), angle.sin());
+        spawn_player(
+            &mut commands,
+            &assets,
+            index,
+            Controls::Gamepad(gamepad),
+            position,
+        );
+        info!("Player {} joined with gamepad {}", index + 1, gamepad.0);
     }
 }
 
-fn move_laser(
+/// Unplugging a gamepad drops its player from the game.
+fn leave_players(
     mut commands: Commands,
-    mut lasers: Query<(Entity, &Laser, &mut Transform)>,
-    time: Res<Time>,
+    mut events: EventReader<GamepadEvent>,
+    players: Query<(Entity, &Player, &Controls)>,
 ) {
-    let speed = 10. * time.delta_seconds();
-    for (entity, laser, mut transform) in lasers.iter_mut() {
-        let direction =
-            (transform.rotation * Quat::from_rotation_z(PI / 2.0)).mul_vec3(Vec3::new(1., 0., 0.));
-        transform.translation += speed * direction;
-        if laser.lifetime + laser.origin <= time.time_since_startup() {
-            commands.entity(entity).despawn();
+    for GamepadEvent(gamepad, event) in events.iter() {
+        if *event != GamepadEventType::Disconnected {
+            continue;
+        }
+        for (entity, player, &controls) in players.iter() {
+            if controls == Controls::Gamepad(*gamepad) {
+                commands.entity(entity).despawn_recursive();
+                info!("Player {} left", player.index + 1);
+            }
         }
     }
 }
 
-pub fn move_player(
-    mut query: Query<(&Player, &mut Transform)>,
+#[allow(clippy::too_many_arguments)]
+fn read_player_input(
+    mut players: Query<(&Controls, &mut PlayerInput, &Transform)>,
     keyboard: Res<Input<KeyCode>>,
+    mouse: Res<Input<MouseButton>>,
     mouse_pos: Res<MousePos>,
-    time: Res<Time>,
+    cursor_state: Res<State<CursorState>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
 ) {
-    let (player, mut transform) = query.single_mut();
-    let speed = player.speed * time.delta_seconds();
-    if keyboard.pressed(KeyCode::W) {
-        transform.translation.y += speed;
-    }
-    if keyboard.pressed(KeyCode::S) {
-        transform.translation.y -= speed;
+    for (controls, mut input, transform) in players.iter_mut() {
+        match *controls {
+            Controls::KeyboardMouse => {
+                let axis = |negative, positive| {
+                    keyboard.pressed(positive) as i32 as f32
+                        - keyboard.pressed(negative) as i32 as f32
+                };
+                input.movement =
+                    Vec2::new(axis(KeyCode::A, KeyCode::D), axis(KeyCode::S, KeyCode::W));
+                input.aim = Vec2::new(mouse_pos.x, mouse_pos.y);
+                // Clicks over the UI are for the UI.
+                input.fire = mouse.just_pressed(MouseButton::Left)
+                    && cursor_state.current() == &CursorState::GameCursor;
+            }
+            Controls::Gamepad(gamepad) => {
+                let stick = |x, y| {
+                    Vec2::new(
+                        axes.get(GamepadAxis(gamepad, x)).unwrap_or(0.0),
+                        axes.get(GamepadAxis(gamepad, y)).unwrap_or(0.0),
+                    )
+                };
+                input.movement = stick(GamepadAxisType::LeftStickX, GamepadAxisType::LeftStickY)
+                    .clamp_length_max(1.0);
+                let position = transform.translation.xy();
+                let aim = stick(GamepadAxisType::RightStickX, GamepadAxisType::RightStickY);
+                let direction = if aim.length() > GAMEPAD_AIM_DEAD_ZONE {
+                    aim.normalize()
+                } else {
+                    facing(transform.rotation)
+                };
+                input.aim = position + GAMEPAD_AIM_DISTANCE * direction;
+                input.fire =
+                    buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::RightTrigger2));
+            }
+        }
     }
-    if keyboard.pressed(KeyCode::A) {
-        transform.translation.x -= speed;
+}
+
+pub fn move_player(
+    mut query: Query<(&Player, &PlayerInput, &mut Knockback, &mut Transform)>,
+    free_camera: Res<FreeCamera>,
+    time: Res<Time>,
+) {
+    for (player, input, mut knockback, mut transform) in query.iter_mut() {
+        transform.translation += knockback.extend(0.0) * time.delta_seconds();
+        **knockback *= (-KNOCKBACK_DAMPING * time.delta_seconds()).exp();
+
+        if free_camera.enabled && free_camera.freeze_player {
+            continue;
+        }
+
+        let speed = player.speed * time.delta_seconds();
+        transform.translation += (input.movement * speed).extend(0.0);
+
+        let (x, y, _) = transform.translation.into();
+        let (dx, dy) = (input.aim.x - x, input.aim.y - y);
+        let angle = dy.atan2(dx);
+        transform.rotation = Quat::from_rotation_z(angle - PI / 2.0);
     }
-    if keyboard.pressed(KeyCode::D) {
-        transform.translation.x += speed;
+}
+
+/// The player closest to `position`, if there are any.
+pub fn nearest_player(players: impl IntoIterator<Item = Vec2>, position: Vec2) -> Option<Vec2> {
+    players.into_iter().min_by(|a, b| {
+        a.distance_squared(position)
+            .total_cmp(&b.distance_squared(position))
+    })
+}
+
+fn knock_back_player(
+    mut collisions: EventReader<CollisionEvent>,
+    mut players: Query<(&Transform, &mut Knockback), With<Player>>,
+    rocks: Query<(&Transform, &Velocity), With<Rock>>,
+) {
+    for collision in collisions.iter() {
+        let (player_tf, mut knockback) = match players.get_mut(collision.entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let (rock_tf, rock_velocity) = match rocks.get(collision.other) {
+            Ok(rock) => rock,
+            Err(_) => continue,
+        };
+
+        let away = (player_tf.translation.xy() - rock_tf.translation.xy()).normalize_or_zero();
+        **knockback = away * (KNOCKBACK_SPEED + rock_velocity.dot(away).max(0.0));
     }
+}
 
-    let (x, y, _) = transform.translation.into();
-    let (dx, dy) = (mouse_pos.x - x, mouse_pos.y - y);
-    let angle = dy.atan2(dx);
-    transform.rotation = Quat::from_rotation_z(angle - PI / 2.0);
+fn damage_player(
+    mut hits: EventReader<Hit>,
+    mut players: Query<&mut Health, With<Player>>,
+    mut shakes: EventWriter<CameraShake>,
+    mut camera_effects: ResMut<CameraEffects>,
+) {
+    for hit in hits.iter() {
+        if let Ok(mut health) = players.get_mut(hit.target) {
+            health.current = (health.current - hit.damage).max(0.0);
+            shakes.send(CameraShake { trauma: 0.5 });
+            camera_effects.push(CameraEffect::new(
+                CameraEffectKind::Flash {
+                    color: Color::rgba(1.0, 0.0, 0.0, 0.3),
+                },
+                0.25,
+            ));
+        }
+    }
 }
@@ -0,0 +1,221 @@
+use bevy::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridHit {
+    pub tile: IVec2,
+    pub distance: f32,
+    pub normal: Vec2,
+}
+
+/// Distance along the ray to the first intersection with the circle, if any.
+/// `direction` must be normalized. A ray starting inside the circle hits at 0.
+pub fn ray_circle(origin: Vec2, direction: Vec2, center: Vec2, radius: f32) -> Option<f32> {
+    let to_origin = origin - center;
+    let c = to_origin.length_squared() - radius * radius;
+    if c <= 0.0 {
+        return Some(0.0);
+    }
+    let b = to_origin.dot(direction);
+    if b > 0.0 {
+        return None;
+    }
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    Some(-b - discriminant.sqrt())
+}
+
+/// Distance from `start` to the first point on the segment inside the circle, if any.
+pub fn segment_circle(start: Vec2, end: Vec2, center: Vec2, radius: f32) -> Option<f32> {
+    let length = start.distance(end);
+    if length <= f32::EPSILON {
+        return (start.distance_squared(center) <= radius * radius).then_some(0.0);
+    }
+    ray_circle(start, (end - start) / length, center, radius).filter(|&d| d <= length)
+}
+
+/// Reflects `direction` about a surface with the given (unit) normal.
+pub fn reflect(direction: Vec2, normal: Vec2) -> Vec2 {
+    direction - 2.0 * direction.dot(normal) * normal
+}
+
+/// The tile containing `point`. Tiles are unit squares centered on integer coordinates.
+pub fn tile_at(point: Vec2) -> IVec2 {
+    (point + Vec2::splat(0.5)).floor().as_ivec2()
+}
+
+/// Normal pointing out of the tile towards `center` and how far the circle
+/// overlaps it, if it does.
+pub fn tile_contact(center: Vec2, radius: f32, tile: IVec2) -> Option<(Vec2, f32)> {
+    let tile_center = tile.as_vec2();
+    let closest = center.clamp(
+        tile_center - Vec2::splat(0.5),
+        tile_center + Vec2::splat(0.5),
+    );
+    let offset = center - closest;
+    let distance = offset.length();
+    if distance >= radius {
+        return None;
+    }
+    let normal = offset
+        .try_normalize()
+        .unwrap_or_else(|| (center - tile_center).try_normalize().unwrap_or(Vec2::Y));
+    Some((normal, radius - distance))
+}
+
+/// Walks the tile grid along the ray (Amanatides & Woo DDA) and returns the first
+/// tile for which `is_solid` holds within `max_distance`. `direction` must be normalized.
+/// Returns `None` for a zero direction or a non-finite `max_distance`, since the walk
+/// would never end.
+pub fn ray_grid(
+    origin: Vec2,
+    direction: Vec2,
+    max_distance: f32,
+    is_solid: impl Fn(IVec2) -> bool,
+) -> Option<GridHit> {
+    if !max_distance.is_finite() || !direction.is_finite() || direction == Vec2::ZERO {
+        return None;
+    }
+    let mut tile = tile_at(origin);
+    if is_solid(tile) {
+        return Some(GridHit {
+            tile,
+            distance: 0.0,
+            normal: -direction,
+        });
+    }
+
+    // Work in a space where tile `t` covers [t, t + 1).
+    let start = origin + Vec2::splat(0.5);
+    let step = IVec2::new(direction.x.signum() as i32, direction.y.signum() as i32);
+    let delta = Vec2::new((1.0 / direction.x).abs(), (1.0 / direction.y).abs());
+    let boundary = |pos: f32, tile: i32, dir: f32| {
+        if dir > 0.0 {
+            (tile as f32 + 1.0 - pos) / dir
+        } else if dir < 0.0 {
+            (tile as f32 - pos) / dir
+        } else {
+            f32::INFINITY
+        }
+    };
+    let mut next = Vec2::new(
+        boundary(start.x, tile.x, direction.x),
+        boundary(start.y, tile.y, direction.y),
+    );
+
+    loop {
+        let (distance, normal) = if next.x < next.y {
+            tile.x += step.x;
+            let distance = next.x;
+            next.x += delta.x;
+            (distance, Vec2::new(-step.x as f32, 0.0))
+        } else {
+            tile.y += step.y;
+            let distance = next.y;
+            next.y += delta.y;
+            (distance, Vec2::new(0.0, -step.y as f32))
+        };
+
+        if distance > max_distance {
+            return None;
+        }
+        if is_solid(tile) {
+            return Some(GridHit {
+                tile,
+                distance,
+                normal,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    #[test]
+    fn ray_circle_hits_front_of_circle() {
+        let hit = ray_circle(Vec2::ZERO, Vec2::X, Vec2::new(5.0, 0.0), 1.0);
+        assert!((hit.unwrap() - 4.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_circle_misses_circle_off_to_the_side() {
+        assert_eq!(
+            ray_circle(Vec2::ZERO, Vec2::X, Vec2::new(5.0, 2.0), 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_circle_misses_circle_behind_origin() {
+        assert_eq!(
+            ray_circle(Vec2::ZERO, Vec2::X, Vec2::new(-5.0, 0.0), 1.0),
+            None
+        );
+    }
+
+    #[test]
+    fn ray_circle_hits_at_zero_from_inside() {
+        let hit = ray_circle(Vec2::new(0.2, 0.1), Vec2::Y, Vec2::ZERO, 1.0);
+        assert_eq!(hit, Some(0.0));
+    }
+
+    #[test]
+    fn ray_circle_grazes_tangent() {
+        let hit = ray_circle(Vec2::new(0.0, 1.0), Vec2::X, Vec2::new(5.0, 0.0), 1.0);
+        assert!((hit.unwrap() - 5.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_grid_walks_along_x_axis() {
+        let hit = ray_grid(Vec2::ZERO, Vec2::X, 10.0, |t| t == IVec2::new(3, 0)).unwrap();
+        assert_eq!(hit.tile, IVec2::new(3, 0));
+        assert!((hit.distance - 2.5).abs() < EPSILON);
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn ray_grid_walks_along_negative_y_axis() {
+        let hit = ray_grid(Vec2::ZERO, -Vec2::Y, 10.0, |t| t == IVec2::new(0, -2)).unwrap();
+        assert_eq!(hit.tile, IVec2::new(0, -2));
+        assert!((hit.distance - 1.5).abs() < EPSILON);
+        assert_eq!(hit.normal, Vec2::Y);
+    }
+
+    #[test]
+    fn ray_grid_stops_at_max_distance() {
+        assert_eq!(ray_grid(Vec2::ZERO, Vec2::X, 2.0, |t| t.x == 3), None);
+    }
+
+    #[test]
+    fn ray_grid_hits_wall_diagonally() {
+        // A vertical wall at x = 2, reached through the tiles below and left of it.
+        let direction = Vec2::new(1.0, 1.0).normalize();
+        let hit = ray_grid(Vec2::new(0.1, 0.0), direction, 10.0, |t| t.x == 2).unwrap();
+        assert_eq!(hit.tile, IVec2::new(2, 1));
+        assert_eq!(hit.normal, Vec2::new(-1.0, 0.0));
+        let point = Vec2::new(0.1, 0.0) + direction * hit.distance;
+        assert!((point.x - 1.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn ray_grid_reports_solid_origin() {
+        let hit = ray_grid(Vec2::ZERO, Vec2::X, 10.0, |_| true).unwrap();
+        assert_eq!(hit.tile, IVec2::ZERO);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn ray_grid_rejects_degenerate_input() {
+        assert_eq!(ray_grid(Vec2::ZERO, Vec2::ZERO, 10.0, |_| false), None);
+        assert_eq!(
+            ray_grid(Vec2::ZERO, Vec2::X, f32::INFINITY, |_| false),
+            None
+        );
+        assert_eq!(ray_grid(Vec2::ZERO, Vec2::X, f32::NAN, |_| false), None);
+    }
+}
@@ -0,0 +1,389 @@
+use bevy::core::FixedTimestep;
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+use crate::camera_effects::{CameraEffect, CameraEffectKind, CameraEffects, CameraShake};
+use crate::collision::{Collider, Layers, SpatialGrid};
+use crate::debug::{DebugCircle, DebugRect};
+use crate::loot::LootRng;
+use crate::raycast::{reflect, tile_at, tile_contact};
+use crate::selection::Selectable;
+use crate::spawner::SpawnedBy;
+use crate::tilemap::TileMap;
+use crate::weapon::{Hit, HitsResolved};
+
+const ROCK_SPRITE: &str = "rock.png";
+
+/// Rock physics runs on a fixed step so that it plays out the same regardless of frame rate.
+pub const PHYSICS_STEP: f32 = 1.0 / 60.0;
+
+pub struct RockPlugin;
+
+impl Plugin for RockPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RockDestroyed>()
+            .init_resource::<LootRng>()
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(FixedTimestep::step(PHYSICS_STEP as f64))
+                    .with_system(move_rocks.label(RocksMoved))
+                    .with_system(collide_rocks.label(RocksCollided).after(RocksMoved))
+                    .with_system(bounce_rocks_off_walls.after(RocksCollided)),
+            )
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                damage_rocks.label(RocksDamaged).after(HitsResolved),
+            );
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct RocksDamaged;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct RocksMoved;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct RocksCollided;
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum RockSize {
+    Small,
+    #[default]
+    Medium,
+    Large,
+}
+
+impl RockSize {
+    pub fn radius(&self) -> f32 {
+        match self {
+            RockSize::Small => 0.125,
+            RockSize::Medium => 0.25,
+            RockSize::Large => 0.5,
+        }
+    }
+
+    /// Rocks are uniformly dense discs, so mass goes with area.
+    pub fn mass(&self) -> f32 {
+        self.radius() * self.radius()
+    }
+
+    pub fn hit_points(&self) -> f32 {
+        match self {
+            RockSize::Small => 1.0,
+            RockSize::Medium => 2.0,
+            RockSize::Large => 4.0,
+        }
+    }
+
+    /// The tier and number of rocks this one breaks into, if any.
+    fn fragments(&self) -> Option<(RockSize, u32)> {
+        match self {
+            RockSize::Small => None,
+            RockSize::Medium => Some((RockSize::Small, 2)),
+            RockSize::Large => Some((RockSize::Medium, 3)),
+        }
+    }
+}
+
+#[derive(Component, Inspectable)]
+pub struct Rock {
+    pub size: RockSize,
+    pub hit_points: f32,
+}
+
+#[derive(Component, Inspectable, Clone, Copy, Default, Deref, DerefMut)]
+pub struct Velocity(pub Vec2);
+
+#[derive(Component, Inspectable, Clone, Copy, Default, Deref, DerefMut)]
+pub struct AngularVelocity(pub f32);
+
+pub struct RockDestroyed {
+    pub size: RockSize,
+    pub position: Vec2,
+    pub velocity: Vec2,
+}
+
+pub fn spawn_rock(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    size: RockSize,
+    position: Vec2,
+    velocity: Vec2,
+    angular_velocity: f32,
+) -> Entity {
+    let radius = size.radius();
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform {
+                translation: position.extend(0.1),
+                scale: Vec3::splat(radius),
+                ..Default::default()
+            },
+            texture: assets.load(ROCK_SPRITE),
+            sprite: Sprite {
+                custom_size: Some((1.0, 1.0).into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Rock {
+            size,
+            hit_points: size.hit_points(),
+        })
+        .insert(Velocity(velocity))
+        .insert(AngularVelocity(angular_velocity))
+        .insert(Collider {
+            radius,
+            layers: Layers::TERRAIN,
+            mask: Layers::empty(),
+        })
+        .insert(DebugCircle {
+            color: Color::BLUE,
+            radius,
+        })
+        .insert(DebugRect {
+            color: Color::GREEN,
+            rotation: 0.0,
+            size: Vec2::splat(radius),
+        })
+        .insert(Selectable)
+        .insert(Name::new("Rock"))
+        .id()
+}
+
+fn move_rocks(mut rocks: Query<(&Velocity, &AngularVelocity, &mut Transform), With<Rock>>) {
+    for (velocity, angular_velocity, mut transform) in rocks.iter_mut() {
+        transform.translation += velocity.extend(0.0) * PHYSICS_STEP;
+        transform.rotate(Quat::from_rotation_z(**angular_velocity * PHYSICS_STEP));
+    }
+}
+
+/// Velocities of two discs after a perfectly elastic collision, or `None` if
+/// they are already moving apart.
+pub fn elastic_collision(
+    (p1, v1, m1): (Vec2, Vec2, f32),
+    (p2, v2, m2): (Vec2, Vec2, f32),
+) -> Option<(Vec2, Vec2)> {
+    let normal = (p2 - p1).try_normalize()?;
+    let approach = (v1 - v2).dot(normal);
+    if approach <= 0.0 {
+        return None;
+    }
+    let impulse = 2.0 * approach / (1.0 / m1 + 1.0 / m2);
+    Some((v1 - impulse / m1 * normal, v2 + impulse / m2 * normal))
+}
+
+fn collide_rocks(mut rocks: Query<(Entity, &Rock, &mut Velocity, &mut Transform)>) {
+    // Work on a sorted copy so the result doesn't depend on query iteration order.
+    let mut bodies: Vec<_> = rocks
+        .iter()
+        .map(|(entity, rock, velocity, tf)| (entity, tf.translation.xy(), **velocity, rock.size))
+        .collect();
+    bodies.sort_by_key(|(entity, ..)| *entity);
+
+    let mut grid = SpatialGrid::new(1.0);
+    let mut index = HashMap::default();
+    for (i, &(entity, position, _, size)) in bodies.iter().enumerate() {
+        grid.insert(entity, position, size.radius(), Layers::TERRAIN);
+        index.insert(entity, i);
+    }
+
+    for i in 0..bodies.len() {
+        let (_, position, _, size) = bodies[i];
+        let mut others: Vec<_> = grid
+            .query_circle(position, size.radius(), Layers::TERRAIN)
+            .map(|other| index[&other])
+            .filter(|&j| j > i)
+            .collect();
+        others.sort_unstable();
+
+        for j in others {
+            let (_, p1, v1, s1) = bodies[i];
+            let (_, p2, v2, s2) = bodies[j];
+            let (m1, m2) = (s1.mass(), s2.mass());
+
+            // Push the pair apart so they don't stay stuck inside each other.
+            let overlap = s1.radius() + s2.radius() - p1.distance(p2);
+            if let Some(normal) = (p2 - p1).try_normalize() {
+                let total = m1 + m2;
+                bodies[i].1 -= overlap * m2 / total * normal;
+                bodies[j].1 += overlap * m1 / total * normal;
+            }
+
+            if let Some((v1, v2)) = elastic_collision((p1, v1, m1), (p2, v2, m2)) {
+                bodies[i].2 = v1;
+                bodies[j].2 = v2;
+            }
+        }
+    }
+
+    for (entity, position, new_velocity, _) in bodies {
+        if let Ok((_, _, mut velocity, mut tf)) = rocks.get_mut(entity) {
+            **velocity = new_velocity;
+            tf.translation = position.extend(tf.translation.z);
+        }
+    }
+}
+
+fn bounce_rocks_off_walls(
+    mut rocks: Query<(&Rock, &mut Velocity, &mut Transform)>,
+    tilemap: Res<TileMap>,
+) {
+    for (rock, mut velocity, mut tf) in rocks.iter_mut() {
+        let radius = rock.size.radius();
+        let center = tile_at(tf.translation.xy());
+        for x in -1..=1 {
+            for y in -1..=1 {
+                let tile = center + IVec2::new(x, y);
+                if !tilemap.is_solid(tile) {
+                    continue;
+                }
+                if let Some((normal, depth)) = tile_contact(tf.translation.xy(), radius, tile) {
+                    tf.translation += (depth * normal).extend(0.0);
+                    if velocity.dot(normal) < 0.0 {
+                        **velocity = reflect(**velocity, normal);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn damage_rocks(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut hits: EventReader<Hit>,
+    mut destructions: EventWriter<RockDestroyed>,
+    mut shakes: EventWriter<CameraShake>,
+    mut camera_effects: ResMut<CameraEffects>,
+    mut rng: ResMut<LootRng>,
+    mut rocks: Query<(&mut Rock, &Transform, &Velocity, Option<&SpawnedBy>)>,
+) {
+    let mut destroyed = HashSet::new();
+    for hit in hits.iter() {
+        if destroyed.contains(&hit.target) {
+            continue;
+        }
+        let (mut rock, transform, velocity, spawned_by) = match rocks.get_mut(hit.target) {
+            Ok(rock) => rock,
+            Err(_) => continue,
+        };
+
+        rock.hit_points -= hit.damage;
+        if rock.hit_points > 0.0 {
+            continue;
+        }
+
+        destroyed.insert(hit.target);
+        commands.entity(hit.target).despawn_recursive();
+
+        shakes.send(CameraShake {
+            trauma: rock.size.radius(),
+        });
+        if rock.size == RockSize::Large {
+            camera_effects.push(CameraEffect::new(
+                CameraEffectKind::ZoomPunch { amount: 0.05 },
+                0.2,
+            ));
+        }
+
+        let center = transform.translation.xy();
+        destructions.send(RockDestroyed {
+            size: rock.size,
+            position: center,
+            velocity: **velocity,
+        });
+
+        if let Some((size, count)) = rock.size.fragments() {
+            let offset = TAU * rng.gen::<f32>();
+            for i in 0..count {
+                let angle = offset + TAU * i as f32 / count as f32;
+                let direction = Vec2::new(angle.cos(), angle.sin());
+                let speed = 1.0 + 0.5 * rng.gen::<f32>();
+                let fragment = spawn_rock(
+                    &mut commands,
+                    &assets,
+                    size,
+                    center + size.radius() * direction,
+                    **velocity + speed * direction,
+                    4.0 * rng.gen::<f32>() - 2.0,
+                );
+                // Fragments count against their nest's cap, and not towards a wave.
+                if let Some(&spawned_by) = spawned_by {
+                    commands.entity(fragment).insert(spawned_by);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn momentum(bodies: &[(Vec2, f32)]) -> Vec2 {
+        bodies.iter().fold(Vec2::ZERO, |sum, &(v, m)| sum + m * v)
+    }
+
+    fn kinetic_energy(bodies: &[(Vec2, f32)]) -> f32 {
+        bodies
+            .iter()
+            .map(|&(v, m)| 0.5 * m * v.length_squared())
+            .sum()
+    }
+
+    fn assert_conserved(a: (Vec2, Vec2, f32), b: (Vec2, Vec2, f32)) {
+        let (v1, v2) = elastic_collision(a, b).expect("bodies should be approaching");
+        let before = [(a.1, a.2), (b.1, b.2)];
+        let after = [(v1, a.2), (v2, b.2)];
+        assert!(momentum(&before).distance(momentum(&after)) < 1e-4);
+        assert!((kinetic_energy(&before) - kinetic_energy(&after)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn equal_masses_swap_velocities_head_on() {
+        let (v1, v2) = elastic_collision(
+            (Vec2::ZERO, Vec2::X, 1.0),
+            (Vec2::new(1.0, 0.0), -Vec2::X, 1.0),
+        )
+        .unwrap();
+        assert!(v1.distance(-Vec2::X) < 1e-5);
+        assert!(v2.distance(Vec2::X) < 1e-5);
+    }
+
+    #[test]
+    fn equal_masses_conserve_momentum_and_energy() {
+        assert_conserved(
+            (Vec2::ZERO, Vec2::new(2.0, 0.5), 1.0),
+            (Vec2::new(0.8, 0.6), Vec2::new(-1.0, 0.0), 1.0),
+        );
+    }
+
+    #[test]
+    fn unequal_masses_conserve_momentum_and_energy() {
+        assert_conserved(
+            (Vec2::ZERO, Vec2::new(3.0, -1.0), 0.25),
+            (Vec2::new(0.5, -0.2), Vec2::new(-0.5, 0.5), 4.0),
+        );
+        assert_conserved(
+            (Vec2::new(-1.0, 2.0), Vec2::new(0.0, -2.0), 9.0),
+            (Vec2::new(-0.7, 1.1), Vec2::ZERO, 1.0),
+        );
+    }
+
+    #[test]
+    fn separating_bodies_are_left_alone() {
+        let result = elastic_collision(
+            (Vec2::ZERO, -Vec2::X, 1.0),
+            (Vec2::new(1.0, 0.0), Vec2::X, 2.0),
+        );
+        assert_eq!(result, None);
+    }
+}
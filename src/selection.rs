@@ -0,0 +1,205 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use bevy_inspector_egui::options::EntityAttributes;
+use bevy_inspector_egui::world_inspector::entity_name;
+use bevy_inspector_egui::{Context, Inspectable};
+
+use crate::collision::Collider;
+use crate::cursor::{CursorState, MousePos, UpdatedMousePos};
+use crate::debug::DebugRect;
+
+/// Drags shorter than this count as a click on whatever is under the cursor.
+const CLICK_DISTANCE: f32 = 0.1;
+/// How close a click has to land to an entity without a collider to select it.
+const CLICK_RADIUS: f32 = 0.3;
+/// Space left between a selected entity and its highlight.
+const HIGHLIGHT_MARGIN: f32 = 0.1;
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+            .add_system(select_entities.label(Selected).after(UpdatedMousePos))
+            .add_system(place_highlights.after(Selected))
+            .add_system(selection_panel.exclusive_system().at_end());
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct Selected;
+
+/// Lets an entity be picked with the selection box and inspected in the side panel.
+#[derive(Component, Clone, Copy, Default)]
+pub struct Selectable;
+
+/// The entities picked with the selection box, in the order they were picked.
+#[derive(Default)]
+pub struct Selection {
+    pub entities: Vec<Entity>,
+}
+
+/// The box being dragged out with the right mouse button.
+#[derive(Component)]
+struct SelectionBox {
+    start: Vec2,
+}
+
+/// Outlines a selected entity. Kept separate since some entities draw a rect of their own.
+#[derive(Component)]
+struct Highlight {
+    target: Entity,
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn select_entities(
+    mut commands: Commands,
+    mut selection: ResMut<Selection>,
+    mut boxes: Query<(Entity, &SelectionBox, &mut Transform, &mut DebugRect)>,
+    highlights: Query<Entity, With<Highlight>>,
+    selectables: Query<
+        (Entity, &Transform, Option<&Collider>),
+        (With<Selectable>, Without<SelectionBox>),
+    >,
+    mouse: Res<Input<MouseButton>>,
+    keyboard: Res<Input<KeyCode>>,
+    mouse_pos: Res<MousePos>,
+    cursor_state: Res<State<CursorState>>,
+) {
+    let cursor = Vec2::new(mouse_pos.x, mouse_pos.y);
+
+    // Forget anything that has been despawned since it was picked.
+    selection
+        .entities
+        .retain(|&entity| selectables.get(entity).is_ok());
+
+    if mouse.just_pressed(MouseButton::Right) && cursor_state.current() == &CursorState::GameCursor
+    {
+        commands
+            .spawn()
+            .insert(SelectionBox { start: cursor })
+            .insert(Transform::from_translation(cursor.extend(0.0)))
+            .insert(DebugRect {
+                color: Color::WHITE,
+                rotation: 0.0,
+                size: Vec2::ZERO,
+            })
+            .insert(Name::new("Selection Box"));
+        return;
+    }
+
+    let (entity, selection_box, mut transform, mut rect) = match boxes.get_single_mut() {
+        Ok(selection_box) => selection_box,
+        Err(_) => return,
+    };
+    let (min, max) = (
+        selection_box.start.min(cursor),
+        selection_box.start.max(cursor),
+    );
+    transform.translation = ((min + max) / 2.0).extend(0.0);
+    rect.size = max - min;
+
+    if !mouse.just_released(MouseButton::Right) {
+        return;
+    }
+    commands.entity(entity).despawn();
+
+    let picked: Vec<Entity> = if selection_box.start.distance(cursor) < CLICK_DISTANCE {
+        selectables
+            .iter()
+            .filter_map(|(entity, tf, collider)| {
+                let reach = collider.map_or(CLICK_RADIUS, |c| c.radius);
+                let distance = tf.translation.xy().distance(cursor);
+                (distance <= reach).then_some((entity, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(entity, _)| entity)
+            .into_iter()
+            .collect()
+    } else {
+        selectables
+            .iter()
+            .filter(|(_, tf, _)| {
+                let position = tf.translation.xy();
+                position.cmpge(min).all() && position.cmple(max).all()
+            })
+            .map(|(entity, _, _)| entity)
+            .collect()
+    };
+
+    // Shift adds to the selection instead of replacing it.
+    if !keyboard.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        selection.entities.clear();
+    }
+    for entity in picked {
+        if !selection.entities.contains(&entity) {
+            selection.entities.push(entity);
+        }
+    }
+
+    for highlight in highlights.iter() {
+        commands.entity(highlight).despawn();
+    }
+    for &target in selection.entities.iter() {
+        let (_, tf, collider) = selectables.get(target).unwrap();
+        let radius = collider.map_or(CLICK_RADIUS, |c| c.radius);
+        commands
+            .spawn()
+            .insert(Highlight { target })
+            .insert(Transform::from_translation(tf.translation.xy().extend(0.0)))
+            .insert(DebugRect {
+                color: Color::YELLOW,
+                rotation: 0.0,
+                size: Vec2::splat(2.0 * (radius + HIGHLIGHT_MARGIN)),
+            })
+            .insert(Name::new("Selection Highlight"));
+    }
+}
+
+fn place_highlights(
+    mut commands: Commands,
+    mut highlights: Query<(Entity, &Highlight, &mut Transform)>,
+    targets: Query<&Transform, Without<Highlight>>,
+) {
+    for (entity, highlight, mut transform) in highlights.iter_mut() {
+        match targets.get(highlight.target) {
+            Ok(target) => transform.translation = target.translation.xy().extend(0.0),
+            Err(_) => commands.entity(entity).despawn(),
+        }
+    }
+}
+
+/// Shows the components of everything selected, like the world inspector does.
+fn selection_panel(world: &mut World) {
+    let selected = world.resource::<Selection>().entities.clone();
+    if selected.is_empty() {
+        return;
+    }
+    let ctx = world.resource_mut::<EguiContext>().ctx_mut().clone();
+
+    egui::SidePanel::right("selection")
+        .resizable(true)
+        .show(&ctx, |ui| {
+            ui.heading(format!("Selected ({})", selected.len()));
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                let mut context = Context::new_world_access(Some(&ctx), world);
+                for (i, mut entity) in selected.into_iter().enumerate() {
+                    let name = context
+                        .world()
+                        .map_or_else(|| format!("{:?}", entity), |w| entity_name(w, entity));
+                    egui::CollapsingHeader::new(name)
+                        .id_source(entity)
+                        .default_open(i == 0)
+                        .show(ui, |ui| {
+                            entity.ui(
+                                ui,
+                                EntityAttributes { despawnable: false },
+                                &mut context.with_id(i as u64),
+                            );
+                        });
+                }
+            });
+        });
+}
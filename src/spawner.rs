@@ -0,0 +1,294 @@
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_inspector_egui::Inspectable;
+use serde::Deserialize;
+
+use crate::collision::{Collider, Layers};
+use crate::cursor::{
+    CursorRequests, CursorStyle, CursorStyled, MousePos, UpdatedMousePos, PLACEMENT_PRIORITY,
+};
+use crate::debug::DebugCircle;
+use crate::enemy::{spawn_enemy, EnemyKind};
+use crate::player::Player;
+use crate::raycast::tile_at;
+use crate::rock::{spawn_rock, RockSize};
+use crate::selection::Selectable;
+use crate::tilemap::TileMap;
+use crate::weapon::{Hit, HitsResolved};
+
+const SPAWNER_MAP: &str = include_str!("../assets/spawners.ron");
+const SPAWNER_SPRITE: &str = "rock.png";
+
+const SPAWNER_RADIUS: f32 = 0.4;
+
+pub struct SpawnerPlugin;
+
+impl Plugin for SpawnerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SpawnerMap::from_ron(SPAWNER_MAP))
+            .add_startup_system_to_stage(StartupStage::PostStartup, place_spawners)
+            .add_system(
+                place_spawner_at_cursor
+                    .after(UpdatedMousePos)
+                    .before(CursorStyled),
+            )
+            .add_system(run_spawners)
+            .add_system_to_stage(CoreStage::PostUpdate, damage_spawners.after(HitsResolved));
+    }
+}
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SpawnKind {
+    /// A rock nest.
+    Rocks { size: RockSize },
+    /// An enemy hive.
+    Enemies { kind: EnemyKind },
+}
+
+impl Default for SpawnKind {
+    fn default() -> Self {
+        SpawnKind::Rocks {
+            size: RockSize::default(),
+        }
+    }
+}
+
+impl SpawnKind {
+    fn is_rocks(&self) -> bool {
+        matches!(self, SpawnKind::Rocks { .. })
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            SpawnKind::Rocks { .. } => Color::rgb(0.6, 0.4, 0.2),
+            SpawnKind::Enemies { .. } => Color::PURPLE,
+        }
+    }
+}
+
+/// A spawner as written in the map file, placed on a tile.
+#[derive(Deserialize, Clone, Debug)]
+pub struct SpawnerDef {
+    pub tile: (i32, i32),
+    pub spawns: SpawnKind,
+    pub interval: f32,
+    pub cap: u32,
+    pub activation_radius: f32,
+    pub hit_points: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct SpawnerMap {
+    pub spawners: Vec<SpawnerDef>,
+}
+
+impl SpawnerMap {
+    pub fn from_ron(source: &str) -> Self {
+        ron::from_str(source).expect("invalid spawner map")
+    }
+}
+
+/// Periodically spawns rocks or enemies while the player is within `activation_radius`,
+/// keeping at most `cap` of them alive at once.
+#[derive(Component, Inspectable)]
+pub struct Spawner {
+    pub spawns: SpawnKind,
+    /// Seconds between spawns.
+    pub interval: f32,
+    pub cap: u32,
+    pub activation_radius: f32,
+    pub hit_points: f32,
+    ready_in: f32,
+}
+
+/// Links a spawned rock or enemy back to the spawner that made it.
+#[derive(Component, Clone, Copy)]
+pub struct SpawnedBy(pub Entity);
+
+pub fn spawn_spawner(
+    commands: &mut Commands,
+    assets: &AssetServer,
+    def: &SpawnerDef,
+    position: Vec2,
+) -> Entity {
+    let color = def.spawns.color();
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform::from_translation(position.extend(0.05)),
+            texture: assets.load(SPAWNER_SPRITE),
+            sprite: Sprite {
+                color,
+                custom_size: Some(Vec2::splat(2.0 * SPAWNER_RADIUS)),
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(Spawner {
+            spawns: def.spawns,
+            interval: def.interval,
+            cap: def.cap,
+            activation_radius: def.activation_radius,
+            hit_points: def.hit_points,
+            ready_in: def.interval,
+        })
+        .insert(Collider {
+            radius: SPAWNER_RADIUS,
+            layers: Layers::ENEMY,
+            mask: Layers::empty(),
+        })
+        .insert(DebugCircle {
+            color,
+            radius: def.activation_radius,
+        })
+        .insert(Selectable)
+        .insert(Name::new("Spawner"))
+        .id()
+}
+
+fn place_spawners(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    map: Res<SpawnerMap>,
+    tilemap: Res<TileMap>,
+) {
+    for def in map.spawners.iter() {
+        let tile = IVec2::new(def.tile.0, def.tile.1);
+        if !tilemap
+            .get_tile(tile)
+            .is_some_and(|kind| kind.is_walkable())
+        {
+            warn!("Skipping spawner on unwalkable tile {}", tile);
+            continue;
+        }
+        spawn_spawner(&mut commands, &assets, def, tile.as_vec2());
+    }
+}
+
+/// Holding N previews a rock nest and H an enemy hive on the tile under the cursor, and
+/// letting go places it there, tuned like the first one of their kind in the map file.
+#[allow(clippy::too_many_arguments)]
+fn place_spawner_at_cursor(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    map: Res<SpawnerMap>,
+    tilemap: Res<TileMap>,
+    mouse_pos: Res<MousePos>,
+    keyboard: Res<Input<KeyCode>>,
+    spawners: Query<&Transform, With<Spawner>>,
+    mut requests: ResMut<CursorRequests>,
+) {
+    let held = |key| keyboard.pressed(key) || keyboard.just_released(key);
+    let (key, rocks) = if held(KeyCode::N) {
+        (KeyCode::N, true)
+    } else if held(KeyCode::H) {
+        (KeyCode::H, false)
+    } else {
+        return;
+    };
+    if mouse_pos.pixel.is_none() {
+        return;
+    }
+    let tile = mouse_pos.tile;
+    let valid = tilemap
+        .get_tile(tile)
+        .is_some_and(|kind| kind.is_walkable())
+        && !spawners
+            .iter()
+            .any(|tf| tile_at(tf.translation.xy()) == tile);
+    if !keyboard.just_released(key) {
+        let style = if valid {
+            CursorStyle::Brush
+        } else {
+            CursorStyle::Invalid
+        };
+        requests.push(style, PLACEMENT_PRIORITY);
+        return;
+    }
+    if !valid {
+        return;
+    }
+    let template = match map
+        .spawners
+        .iter()
+        .find(|def| def.spawns.is_rocks() == rocks)
+    {
+        Some(template) => template,
+        None => return,
+    };
+    let def = SpawnerDef {
+        tile: (tile.x, tile.y),
+        ..template.clone()
+    };
+    spawn_spawner(&mut commands, &assets, &def, tile.as_vec2());
+}
+
+fn run_spawners(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut spawners: Query<(Entity, &mut Spawner, &Transform)>,
+    spawned: Query<&SpawnedBy>,
+    players: Query<&Transform, With<Player>>,
+    time: Res<Time>,
+) {
+    let mut alive: HashMap<Entity, u32> = HashMap::default();
+    for SpawnedBy(spawner) in spawned.iter() {
+        *alive.entry(*spawner).or_default() += 1;
+    }
+
+    for (entity, mut spawner, transform) in spawners.iter_mut() {
+        let position = transform.translation.xy();
+        if !players
+            .iter()
+            .any(|tf| position.distance(tf.translation.xy()) <= spawner.activation_radius)
+        {
+            continue;
+        }
+        spawner.ready_in -= time.delta_seconds();
+        if spawner.ready_in > 0.0 || alive.get(&entity).copied().unwrap_or(0) >= spawner.cap {
+            continue;
+        }
+        spawner.ready_in = spawner.interval;
+
+        let angle = std::f32::consts::TAU * rand::random::<f32>();
+        let offset = SPAWNER_RADIUS * Vec2::new(angle.cos(), angle.sin());
+        let child = match spawner.spawns {
+            SpawnKind::Rocks { size } => {
+                let velocity = (1.0 + rand::random::<f32>()) * offset.normalize_or_zero();
+                let spin = 2.0 * rand::random::<f32>() - 1.0;
+                spawn_rock(
+                    &mut commands,
+                    &assets,
+                    size,
+                    position + offset,
+                    velocity,
+                    spin,
+                )
+            }
+            SpawnKind::Enemies { kind } => {
+                spawn_enemy(&mut commands, &assets, kind, position + offset)
+            }
+        };
+        commands.entity(child).insert(SpawnedBy(entity));
+    }
+}
+
+fn damage_spawners(
+    mut commands: Commands,
+    mut hits: EventReader<Hit>,
+    mut spawners: Query<&mut Spawner>,
+) {
+    let mut destroyed = HashSet::new();
+    for hit in hits.iter() {
+        if destroyed.contains(&hit.target) {
+            continue;
+        }
+        if let Ok(mut spawner) = spawners.get_mut(hit.target) {
+            spawner.hit_points -= hit.damage;
+            if spawner.hit_points <= 0.0 {
+                destroyed.insert(hit.target);
+                commands.entity(hit.target).despawn_recursive();
+            }
+        }
+    }
+}
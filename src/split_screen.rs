@@ -0,0 +1,388 @@
+use bevy::core_pipeline::node::{CLEAR_PASS_DRIVER, MAIN_PASS_DRIVER};
+use bevy::core_pipeline::{draw_2d_graph, RenderTargetClearColors, Transparent2d};
+use bevy::math::{const_vec2, Vec3Swizzles};
+use bevy::prelude::*;
+use bevy::render::camera::{ExtractedCamera, RenderTarget, ScalingMode};
+use bevy::render::render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotValue};
+use bevy::render::render_phase::RenderPhase;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::renderer::RenderContext;
+use bevy::render::view::{ExtractedView, RenderLayers, ViewTarget, VisibleEntities};
+use bevy::render::{RenderApp, RenderStage};
+use bevy::sprite::MaterialMesh2dBundle;
+use bevy::window::WindowId;
+
+use crate::camera_controller::CameraRig;
+use crate::player::Player;
+
+/// Where the window's camera looks at the player views from. Sprites are drawn by
+/// every camera whatever their render layers, so it keeps well clear of the world.
+pub const COMPOSITOR_ORIGIN: Vec2 = const_vec2!([100_000.0, 0.0]);
+/// Render layer that only the window's camera sees.
+const COMPOSITOR_LAYER: u8 = RenderLayers::TOTAL_LAYERS as u8 - 1;
+/// Space between the slices of the window, in logical pixels.
+const SPLIT_GAP: f32 = 2.0;
+
+const PLAYER_VIEWS_DRIVER: &str = "player_views_driver";
+
+pub struct SplitScreenPlugin;
+
+impl Plugin for SplitScreenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system(spawn_compositor)
+            .add_system(sync_player_views.before(ViewsLaidOut))
+            .add_system(lay_out_views.label(ViewsLaidOut));
+
+        let render_app = app.sub_app_mut(RenderApp);
+        render_app.add_system_to_stage(RenderStage::Extract, extract_player_views);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(PLAYER_VIEWS_DRIVER, PlayerViewsDriverNode);
+        graph
+            .add_node_edge(CLEAR_PASS_DRIVER, PLAYER_VIEWS_DRIVER)
+            .unwrap();
+        graph
+            .add_node_edge(PLAYER_VIEWS_DRIVER, MAIN_PASS_DRIVER)
+            .unwrap();
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct ViewsLaidOut;
+
+/// A rectangle of the window, in logical pixels from the bottom left corner.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Viewport {
+    pub min: Vec2,
+    pub size: Vec2,
+}
+
+impl Viewport {
+    pub fn max(&self) -> Vec2 {
+        self.min + self.size
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.min + self.size / 2.0
+    }
+
+    /// `None` while the viewport has no area, like when the window is minimised.
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        (self.size.x > 0.0 && self.size.y > 0.0).then(|| self.size.x / self.size.y)
+    }
+}
+
+/// Splits a window of `window_size` between `count` players: side by side for two,
+/// and into quarters for three or four, filled from the top left.
+pub fn split(count: usize, window_size: Vec2) -> Vec<Viewport> {
+    let columns = if count > 1 { 2 } else { 1 };
+    let rows = if count > 2 { 2 } else { 1 };
+    let gaps = Vec2::new((columns - 1) as f32, (rows - 1) as f32) * SPLIT_GAP;
+    let size = ((window_size - gaps) / Vec2::new(columns as f32, rows as f32)).max(Vec2::ZERO);
+    (0..count)
+        .map(|i| {
+            let (column, row) = ((i % columns) as f32, (i / columns) as f32);
+            Viewport {
+                min: Vec2::new(
+                    column * (size.x + SPLIT_GAP),
+                    window_size.y - (row + 1.0) * size.y - row * SPLIT_GAP,
+                ),
+                size,
+            }
+        })
+        .collect()
+}
+
+/// A camera following one player, rendering into their slice of the window.
+#[derive(Component)]
+pub struct PlayerView {
+    pub player: Entity,
+    /// The index of the player, which also orders the slices.
+    pub index: usize,
+    pub viewport: Viewport,
+    /// Shows the view's image in the window.
+    pane: Entity,
+}
+
+impl PlayerView {
+    /// A view of `player`, shown in the window by `pane`. Laid out by [`lay_out_views`].
+    pub fn new(player: Entity, index: usize, pane: Entity) -> Self {
+        PlayerView {
+            player,
+            index,
+            viewport: Viewport::default(),
+            pane,
+        }
+    }
+
+    /// The layer only this view sees, for things like overlays that are per view.
+    pub fn layer(&self) -> u8 {
+        1 + self.index as u8
+    }
+}
+
+/// The camera drawing the player views into the window.
+#[derive(Component)]
+pub struct Compositor;
+
+fn spawn_compositor(mut commands: Commands, mut clear_colors: ResMut<RenderTargetClearColors>) {
+    // Only the gaps between the slices show this.
+    clear_colors.insert(RenderTarget::Window(WindowId::primary()), Color::BLACK);
+
+    let mut camera = OrthographicCameraBundle::new_2d();
+    camera.transform.translation = COMPOSITOR_ORIGIN.extend(camera.transform.translation.z);
+    commands
+        .spawn_bundle(camera)
+        .insert(RenderLayers::layer(COMPOSITOR_LAYER))
+        .insert(Compositor)
+        .insert(Name::new("Compositor Camera"));
+}
+
+/// An image for a view to render into. Sized by [`lay_out_views`].
+fn view_image() -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::Bgra8UnormSrgb,
+    );
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+    image
+}
+
+/// Gives every player a view, and drops the views of players who left.
+fn sync_player_views(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    players: Query<(Entity, &Player, &Transform)>,
+    views: Query<(Entity, &PlayerView)>,
+) {
+    for (entity, view) in views.iter() {
+        if players.get(view.player).is_err() {
+            commands.entity(view.pane).despawn();
+            commands.entity(entity).despawn();
+        }
+    }
+
+    for (entity, player, transform) in players.iter() {
+        if views.iter().any(|(_, view)| view.player == entity) {
+            continue;
+        }
+        let image = images.add(view_image());
+
+        // A mesh rather than a sprite, since sprites don't respect render layers and
+        // a view must not draw the image it's rendering into.
+        let pane = commands
+            .spawn_bundle(MaterialMesh2dBundle {
+                mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE))).into(),
+                material: materials.add(ColorMaterial::from(image.clone())),
+                ..Default::default()
+            })
+            .insert(RenderLayers::layer(COMPOSITOR_LAYER))
+            .insert(Name::new(format!("Player {} Pane", player.index + 1)))
+            .id();
+
+        let view = PlayerView::new(entity, player.index, pane);
+        let position = transform.translation.xy();
+        let mut camera = OrthographicCameraBundle::new_2d();
+        camera.camera.target = RenderTarget::Image(image);
+        camera.orthographic_projection.scaling_mode = ScalingMode::None;
+        camera.transform.translation = position.extend(camera.transform.translation.z);
+        commands
+            .spawn()
+            .insert(camera.camera)
+            .insert(camera.orthographic_projection)
+            .insert(camera.visible_entities)
+            .insert(camera.frustum)
+            .insert(camera.transform)
+            .insert(camera.global_transform)
+            .insert(RenderLayers::from_layers(&[0, view.layer()]))
+            .insert(CameraRig::at(position))
+            .insert(view)
+            .insert(Name::new(format!("Player {} Camera", player.index + 1)));
+    }
+}
+
+/// Fits the views to the window, and the images they render into to their views.
+pub fn lay_out_views(
+    windows: Res<Windows>,
+    mut images: ResMut<Assets<Image>>,
+    mut views: Query<(&mut PlayerView, &Camera)>,
+    mut panes: Query<&mut Transform, Without<PlayerView>>,
+) {
+    let window = windows.get_primary().unwrap();
+    let window_size = Vec2::new(window.width(), window.height());
+    if window_size.x <= 0.0 || window_size.y <= 0.0 {
+        // Minimised.
+        return;
+    }
+
+    let mut views: Vec<_> = views.iter_mut().collect();
+    views.sort_by_key(|(view, _)| view.index);
+    let viewports = split(views.len(), window_size);
+    for ((mut view, camera), viewport) in views.into_iter().zip(viewports) {
+        // Only on change, so that the projections are only refitted when needed.
+        if view.viewport != viewport {
+            view.viewport = viewport;
+        }
+
+        if let Ok(mut pane) = panes.get_mut(view.pane) {
+            let center = COMPOSITOR_ORIGIN + viewport.center() - window_size / 2.0;
+            pane.translation = center.extend(0.0);
+            pane.scale = viewport.size.extend(1.0);
+        }
+
+        let size = (viewport.size * window.scale_factor() as f32)
+            .round()
+            .as_uvec2()
+            .max(UVec2::ONE);
+        let image = match &camera.target {
+            RenderTarget::Image(image) => image,
+            RenderTarget::Window(_) => continue,
+        };
+        // Resizing marks the image as modified, which makes it be uploaded again.
+        let resized = images.get(image).is_some_and(|image| {
+            let Extent3d { width, height, .. } = image.texture_descriptor.size;
+            UVec2::new(width, height) != size
+        });
+        if resized {
+            images.get_mut(image).unwrap().resize(Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            });
+        }
+    }
+}
+
+/// The views extracted this frame, in the order they're drawn.
+#[derive(Default)]
+struct ExtractedPlayerViews(Vec<Entity>);
+
+/// Bevy only extracts the one active 2D camera, so the player views are extracted
+/// here the same way.
+fn extract_player_views(
+    mut commands: Commands,
+    windows: Res<Windows>,
+    images: Res<Assets<Image>>,
+    views: Query<(
+        Entity,
+        &PlayerView,
+        &Camera,
+        &GlobalTransform,
+        &VisibleEntities,
+    )>,
+) {
+    let mut extracted = Vec::new();
+    for (entity, view, camera, transform, visible_entities) in views.iter() {
+        let size = match camera.target.get_physical_size(&windows, &images) {
+            Some(size) => size,
+            None => continue,
+        };
+        commands.get_or_spawn(entity).insert_bundle((
+            ExtractedCamera {
+                target: camera.target.clone(),
+                physical_size: Some(size),
+            },
+            ExtractedView {
+                projection: camera.projection_matrix,
+                transform: *transform,
+                width: size.x,
+                height: size.y,
+                near: camera.near,
+                far: camera.far,
+            },
+            visible_entities.clone(),
+            RenderPhase::<Transparent2d>::default(),
+        ));
+        extracted.push((view.index, entity));
+    }
+    extracted.sort_unstable();
+    commands.insert_resource(ExtractedPlayerViews(
+        extracted.into_iter().map(|(_, entity)| entity).collect(),
+    ));
+}
+
+/// Draws each player view, before the window's camera draws them to the screen.
+struct PlayerViewsDriverNode;
+
+impl Node for PlayerViewsDriverNode {
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        _render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let views = match world.get_resource::<ExtractedPlayerViews>() {
+            Some(views) => views,
+            None => return Ok(()),
+        };
+        for &view in views.0.iter() {
+            // No target until the image has made it to the GPU.
+            if world.get::<ViewTarget>(view).is_some() {
+                graph.run_sub_graph(draw_2d_graph::NAME, vec![SlotValue::Entity(view)])?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_player_gets_the_whole_window() {
+        let window = Vec2::new(1280.0, 720.0);
+        assert_eq!(
+            split(1, window),
+            vec![Viewport {
+                min: Vec2::ZERO,
+                size: window
+            }]
+        );
+    }
+
+    #[test]
+    fn two_players_split_side_by_side() {
+        let views = split(2, Vec2::new(1280.0, 720.0));
+        let half = (1280.0 - SPLIT_GAP) / 2.0;
+        assert_eq!(views[0].min, Vec2::ZERO);
+        assert_eq!(views[0].size, Vec2::new(half, 720.0));
+        assert_eq!(views[1].min, Vec2::new(half + SPLIT_GAP, 0.0));
+        assert_eq!(views[1].max(), Vec2::new(1280.0, 720.0));
+    }
+
+    #[test]
+    fn more_players_split_into_quarters_from_the_top_left() {
+        let window = Vec2::new(1280.0, 720.0);
+        for count in [3, 4] {
+            let views = split(count, window);
+            assert_eq!(views.len(), count);
+            // The first player is at the top left, the second at the top right.
+            assert_eq!(views[0].min.x, 0.0);
+            assert_eq!(views[0].max().y, 720.0);
+            assert_eq!(views[1].max(), window);
+            assert_eq!(views[2].min, Vec2::ZERO);
+            for (i, a) in views.iter().enumerate() {
+                for b in &views[i + 1..] {
+                    let overlap = a.max().min(b.max()) - a.min.max(b.min);
+                    assert!(overlap.x <= 0.0 || overlap.y <= 0.0, "{:?} {:?}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn minimised_window_gives_empty_views() {
+        for view in split(4, Vec2::ZERO) {
+            assert_eq!(view.aspect_ratio(), None);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use bevy::math::{Mat2, Vec3Swizzles};
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::Inspectable;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::collision::{Layers, SpatialGrid};
+use crate::raycast::{ray_grid, tile_at, tile_contact};
+use crate::rock::Velocity;
+use crate::tilemap::TileMap;
+use crate::weapon::rotation_towards;
+
+/// Angle between the centre feeler and each side feeler in obstacle avoidance.
+const FEELER_ANGLE: f32 = 0.5;
+
+pub struct SteeringPlugin;
+
+impl Plugin for SteeringPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(steer.label(Steered))
+            .add_system(keep_out_of_walls.after(Steered));
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct Steered;
+
+/// Accelerates towards `target` at full speed.
+pub fn seek(position: Vec2, velocity: Vec2, target: Vec2, max_speed: f32) -> Vec2 {
+    (target - position).normalize_or_zero() * max_speed - velocity
+}
+
+/// Accelerates away from `target` while it is closer than `panic_distance`.
+pub fn flee(
+    position: Vec2,
+    velocity: Vec2,
+    target: Vec2,
+    max_speed: f32,
+    panic_distance: f32,
+) -> Vec2 {
+    if position.distance(target) > panic_distance {
+        return Vec2::ZERO;
+    }
+    (position - target).normalize_or_zero() * max_speed - velocity
+}
+
+/// Like [`seek`], but slows down inside `slowing_radius` to come to a stop on `target`.
+pub fn arrive(
+    position: Vec2,
+    velocity: Vec2,
+    target: Vec2,
+    max_speed: f32,
+    slowing_radius: f32,
+) -> Vec2 {
+    let offset = target - position;
+    let distance = offset.length();
+    if distance <= f32::EPSILON {
+        return -velocity;
+    }
+    let speed = max_speed * (distance / slowing_radius).min(1.0);
+    offset / distance * speed - velocity
+}
+
+/// Heads for a point on a circle `distance` ahead of the agent, at `angle` around it.
+/// Jittering `angle` a little every frame gives a smooth random walk.
+pub fn wander(velocity: Vec2, angle: f32, distance: f32, radius: f32, max_speed: f32) -> Vec2 {
+    let heading = velocity.try_normalize().unwrap_or(Vec2::X);
+    let target = heading * distance + radius * Vec2::new(angle.cos(), angle.sin());
+    target.normalize_or_zero() * max_speed - velocity
+}
+
+/// Pushes away from every neighbour closer than `radius`, harder the closer it is.
+pub fn separation(
+    position: Vec2,
+    neighbours: impl IntoIterator<Item = Vec2>,
+    radius: f32,
+    max_speed: f32,
+) -> Vec2 {
+    let push: Vec2 = neighbours
+        .into_iter()
+        .filter_map(|other| {
+            let offset = position - other;
+            let distance = offset.length();
+            (distance > f32::EPSILON && distance < radius)
+                .then(|| offset / distance * (1.0 - distance / radius))
+        })
+        .fold(Vec2::ZERO, |sum, push| sum + push);
+    push.clamp_length_max(1.0) * max_speed
+}
+
+/// Casts three feelers along the heading and steers away from the walls they touch.
+pub fn avoid_obstacles(
+    position: Vec2,
+    velocity: Vec2,
+    look_ahead: f32,
+    max_speed: f32,
+    is_solid: impl Fn(IVec2) -> bool,
+) -> Vec2 {
+    let heading = match velocity.try_normalize() {
+        Some(heading) => heading,
+        None => return Vec2::ZERO,
+    };
+    [0.0, FEELER_ANGLE, -FEELER_ANGLE]
+        .into_iter()
+        .filter_map(|angle| {
+            let feeler = Mat2::from_angle(angle) * heading;
+            ray_grid(position, feeler, look_ahead, &is_solid)
+        })
+        .map(|hit| hit.normal * max_speed * (1.0 - hit.distance / look_ahead))
+        .fold(Vec2::ZERO, |sum, push| sum + push)
+}
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Behaviour {
+    #[default]
+    Seek,
+    Flee {
+        panic_distance: f32,
+    },
+    /// Arrives at the point `standoff` away from the target, on the agent's side of it.
+    Arrive {
+        slowing_radius: f32,
+        standoff: f32,
+    },
+    Wander {
+        distance: f32,
+        radius: f32,
+        jitter: f32,
+    },
+    Separation {
+        radius: f32,
+    },
+    AvoidObstacles {
+        look_ahead: f32,
+    },
+}
+
+#[derive(Inspectable, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Weighted {
+    pub behaviour: Behaviour,
+    pub weight: f32,
+}
+
+/// A weighted blend of steering behaviours. The agent moves by its [`Velocity`].
+#[derive(Component, Inspectable, Clone, Debug)]
+pub struct Steering {
+    pub behaviours: Vec<Weighted>,
+    /// What seek, flee and arrive steer relative to. Those are skipped without one.
+    pub target: Option<Vec2>,
+    pub max_speed: f32,
+    pub max_force: f32,
+    pub radius: f32,
+    wander_angle: f32,
+}
+
+impl Steering {
+    pub fn new(max_speed: f32, max_force: f32, radius: f32) -> Self {
+        Steering {
+            behaviours: Vec::new(),
+            target: None,
+            max_speed,
+            max_force,
+            radius,
+            wander_angle: 0.0,
+        }
+    }
+}
+
+fn steer(
+    mut agents: Query<(Entity, &mut Steering, &mut Velocity, &mut Transform)>,
+    grid: Res<SpatialGrid>,
+    tilemap: Res<TileMap>,
+    time: Res<Time>,
+) {
+    let dt = time.delta_seconds();
+    let positions: HashMap<Entity, Vec2> = agents
+        .iter()
+        .map(|(entity, _, _, tf)| (entity, tf.translation.xy()))
+        .collect();
+    let mut rng = rand::thread_rng();
+
+    for (entity, mut steering, mut velocity, mut transform) in agents.iter_mut() {
+        let position = transform.translation.xy();
+        let max_speed = steering.max_speed;
+        let mut force = Vec2::ZERO;
+        for i in 0..steering.behaviours.len() {
+            let Weighted { behaviour, weight } = steering.behaviours[i];
+            let target = steering.target;
+            let desired = match (behaviour, target) {
+                (Behaviour::Seek, Some(target)) => seek(position, **velocity, target, max_speed),
+                (Behaviour::Flee { panic_distance }, Some(target)) => {
+                    flee(position, **velocity, target, max_speed, panic_distance)
+                }
+                (
+                    Behaviour::Arrive {
+                        slowing_radius,
+                        standoff,
+                    },
+                    Some(target),
+                ) => {
+                    let away = (position - target).normalize_or_zero();
+                    let spot = target + standoff * away;
+                    arrive(position, **velocity, spot, max_speed, slowing_radius)
+                }
+                (
+                    Behaviour::Wander {
+                        distance,
+                        radius,
+                        jitter,
+                    },
+                    _,
+                ) => {
+                    steering.wander_angle += rng.gen_range(-jitter..=jitter) * dt;
+                    wander(
+                        **velocity,
+                        steering.wander_angle,
+                        distance,
+                        radius,
+                        max_speed,
+                    )
+                }
+                (Behaviour::Separation { radius }, _) => {
+                    let neighbours = grid
+                        .query_circle(position, radius, Layers::ENEMY)
+                        .filter(|&other| other != entity)
+                        .filter_map(|other| positions.get(&other).copied());
+                    separation(position, neighbours, radius, max_speed)
+                }
+                (Behaviour::AvoidObstacles { look_ahead }, _) => {
+                    avoid_obstacles(position, **velocity, look_ahead, max_speed, |tile| {
+                        tilemap.is_solid(tile)
+                    })
+                }
+                _ => Vec2::ZERO,
+            };
+            force += weight * desired;
+        }
+
+        let force = force.clamp_length_max(steering.max_force);
+        **velocity = (**velocity + force * dt).clamp_length_max(max_speed);
+        transform.translation += velocity.extend(0.0) * dt;
+        if let Some(heading) = velocity.try_normalize() {
+            transform.rotation = rotation_towards(heading);
+        }
+    }
+}
+
+/// Avoidance is only a suggestion, so anything that still ends up in a wall is pushed out.
+fn keep_out_of_walls(
+    mut agents: Query<(&Steering, &mut Velocity, &mut Transform)>,
+    tilemap: Res<TileMap>,
+) {
+    for (steering, mut velocity, mut tf) in agents.iter_mut() {
+        let center = tile_at(tf.translation.xy());
+        for x in -1..=1 {
+            for y in -1..=1 {
+                let tile = center + IVec2::new(x, y);
+                if !tilemap.is_solid(tile) {
+                    continue;
+                }
+                if let Some((normal, depth)) =
+                    tile_contact(tf.translation.xy(), steering.radius, tile)
+                {
+                    tf.translation += (depth * normal).extend(0.0);
+                    let into_wall = velocity.dot(normal).min(0.0);
+                    **velocity -= into_wall * normal;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    #[test]
+    fn seek_heads_straight_for_target() {
+        let force = seek(Vec2::ZERO, Vec2::ZERO, Vec2::new(0.0, 5.0), 2.0);
+        assert!(force.distance(Vec2::new(0.0, 2.0)) < EPSILON);
+        // Cancels out whatever velocity isn't towards the target.
+        let force = seek(Vec2::ZERO, Vec2::X, Vec2::new(0.0, 5.0), 2.0);
+        assert!(force.distance(Vec2::new(-1.0, 2.0)) < EPSILON);
+    }
+
+    #[test]
+    fn flee_only_inside_panic_distance() {
+        let force = flee(Vec2::ZERO, Vec2::ZERO, Vec2::new(1.0, 0.0), 3.0, 2.0);
+        assert!(force.distance(Vec2::new(-3.0, 0.0)) < EPSILON);
+        assert_eq!(
+            flee(Vec2::ZERO, Vec2::ZERO, Vec2::new(5.0, 0.0), 3.0, 2.0),
+            Vec2::ZERO
+        );
+    }
+
+    #[test]
+    fn arrive_slows_down_near_target() {
+        let far = arrive(Vec2::ZERO, Vec2::ZERO, Vec2::new(10.0, 0.0), 4.0, 2.0);
+        assert!(far.distance(Vec2::new(4.0, 0.0)) < EPSILON);
+        let near = arrive(Vec2::ZERO, Vec2::ZERO, Vec2::new(1.0, 0.0), 4.0, 2.0);
+        assert!(near.distance(Vec2::new(2.0, 0.0)) < EPSILON);
+        // On the target, it brakes to a stop.
+        let there = arrive(Vec2::ONE, Vec2::new(0.5, 0.0), Vec2::ONE, 4.0, 2.0);
+        assert!(there.distance(Vec2::new(-0.5, 0.0)) < EPSILON);
+    }
+
+    #[test]
+    fn wander_keeps_to_the_circle_ahead() {
+        // With the circle straight ahead, wandering at angle 0 just keeps going.
+        let ahead = wander(Vec2::X, 0.0, 2.0, 1.0, 1.0);
+        assert!(ahead.length() < EPSILON);
+        // At a quarter turn the target sits ahead and to the left.
+        let left = wander(Vec2::X, std::f32::consts::FRAC_PI_2, 1.0, 1.0, 2.0);
+        let expected = Vec2::new(1.0, 1.0).normalize() * 2.0 - Vec2::X;
+        assert!(left.distance(expected) < EPSILON);
+    }
+
+    #[test]
+    fn separation_pushes_away_from_close_neighbours() {
+        let push = separation(Vec2::ZERO, [Vec2::new(0.5, 0.0)], 1.0, 2.0);
+        assert!(push.distance(Vec2::new(-1.0, 0.0)) < EPSILON);
+        // Neighbours outside the radius, and the agent itself, are ignored.
+        let none = separation(Vec2::ZERO, [Vec2::new(3.0, 0.0), Vec2::ZERO], 1.0, 2.0);
+        assert_eq!(none, Vec2::ZERO);
+    }
+
+    #[test]
+    fn avoid_obstacles_steers_away_from_wall_ahead() {
+        // A wall along x = 2, with the agent heading straight at it.
+        let push = avoid_obstacles(Vec2::ZERO, Vec2::X, 3.0, 1.0, |tile| tile.x == 2);
+        assert!(push.x < 0.0);
+        assert!(push.y.abs() < EPSILON);
+        // Nothing ahead, or not moving, means nothing to avoid.
+        assert_eq!(
+            avoid_obstacles(Vec2::ZERO, -Vec2::X, 3.0, 1.0, |tile| tile.x == 2),
+            Vec2::ZERO
+        );
+        assert_eq!(
+            avoid_obstacles(Vec2::ZERO, Vec2::ZERO, 3.0, 1.0, |tile| tile.x == 2),
+            Vec2::ZERO
+        );
+    }
+}
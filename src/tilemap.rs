@@ -32,10 +32,25 @@ impl TileKind {
     fn get_sprite(&self) -> &'static str {
         match self {
             TileKind::Grass => "grass.png",
-            TileKind::Stone => "stone.png",
+            TileKind::Stone | TileKind::Wall => "stone.png",
             _ => "grass.png",
         }
     }
+
+    fn get_color(&self) -> Color {
+        match self {
+            TileKind::Wall => Color::rgb(0.3, 0.3, 0.35),
+            _ => Color::WHITE,
+        }
+    }
+
+    pub fn is_solid(&self) -> bool {
+        matches!(self, TileKind::Wall)
+    }
+
+    pub fn is_walkable(&self) -> bool {
+        matches!(self, TileKind::Stone | TileKind::Grass)
+    }
 }
 
 struct TileSprites {
@@ -45,7 +60,7 @@ struct TileSprites {
 impl FromWorld for TileSprites {
     fn from_world(world: &mut World) -> Self {
         Self {
-            sprites: [TileKind::Stone, TileKind::Grass]
+            sprites: [TileKind::Stone, TileKind::Grass, TileKind::Wall]
                 .into_iter()
                 .map(|kind| {
                     (
@@ -66,25 +81,51 @@ pub struct Tile {
 #[derive(Default)]
 pub struct TileMap {
     tiles: Vec<Entity>,
+    kinds: HashMap<IVec2, TileKind>,
     to_be_added: Vec<Tile>,
 }
 
 impl TileMap {
     pub fn set_tile(&mut self, x: i32, y: i32, kind: TileKind) {
+        self.kinds.insert(IVec2::new(x, y), kind.clone());
         self.to_be_added.push(Tile {
             pos: IVec2::new(x, y),
             kind,
         });
     }
+
+    pub fn get_tile(&self, pos: IVec2) -> Option<&TileKind> {
+        self.kinds.get(&pos)
+    }
+
+    pub fn is_solid(&self, pos: IVec2) -> bool {
+        self.get_tile(pos).is_some_and(TileKind::is_solid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (IVec2, &TileKind)> {
+        self.kinds.iter().map(|(pos, kind)| (*pos, kind))
+    }
+
+    /// The lowest and highest tile coordinates in use, or `None` if the map is empty.
+    pub fn extents(&self) -> Option<(IVec2, IVec2)> {
+        let mut positions = self.kinds.keys();
+        let first = *positions.next()?;
+        Some(positions.fold((first, first), |(min, max), &pos| {
+            (min.min(pos), max.max(pos))
+        }))
+    }
 }
 
 fn spawn_tiles(mut tilemap: ResMut<TileMap>) {
-    for x in 0..10 {
-        for y in 0..10 {
+    for x in -1..=10 {
+        for y in -1..=10 {
+            let border = x == -1 || x == 10 || y == -1 || y == 10;
             tilemap.set_tile(
                 x,
                 y,
-                if rand::random::<bool>() {
+                if border {
+                    TileKind::Wall
+                } else if rand::random::<bool>() {
                     TileKind::Grass
                 } else {
                     TileKind::Stone
@@ -122,6 +163,7 @@ fn update_tiles(
                 },
                 texture: tile_sprites.sprites.get(&tile.kind).unwrap().clone(),
                 sprite: Sprite {
+                    color: tile.kind.get_color(),
                     custom_size: Some((1.0, 1.0).into()),
                     ..Default::default()
                 },
@@ -141,16 +183,13 @@ fn update_tiles(
 
 fn red_if_occupied(
     mut tiles: Query<(&Transform, &mut DebugRect), With<TileKind>>,
-    player: Query<&Transform, (With<Player>, Without<TileKind>)>,
+    players: Query<&Transform, (With<Player>, Without<TileKind>)>,
 ) {
-    let player_tf = player.single();
     for (tile_tf, mut rect) in tiles.iter_mut() {
         let tile_xy = tile_tf.translation.xy();
-        let player_xy = player_tf.translation.xy();
-        rect.color = if tile_xy.abs_diff_eq(player_xy, 0.5) {
-            Color::RED
-        } else {
-            Color::BLACK
-        }
+        let occupied = players
+            .iter()
+            .any(|player_tf| tile_xy.abs_diff_eq(player_tf.translation.xy(), 0.5));
+        rect.color = if occupied { Color::RED } else { Color::BLACK }
     }
 }
@@ -0,0 +1,901 @@
+use bevy::input::mouse::MouseWheel;
+use bevy::math::Vec3Swizzles;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use bevy_inspector_egui::Inspectable;
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use crate::camera_controller::{CameraMoved, CameraRig};
+use crate::collision::{Collider, CollisionEvent, CollisionsDetected, Layers, SpatialGrid, Swept};
+use crate::cursor::world_to_screen;
+use crate::debug::{DebugCircle, DebugDraw};
+use crate::player::{Controls, Player, PlayerInput, PlayerMoved};
+use crate::raycast::{ray_grid, reflect};
+use crate::split_screen::PlayerView;
+use crate::tilemap::TileMap;
+
+const LASER_SPRITE: &str = "laser.png";
+
+/// How far above a player their reloading note floats, in world units.
+const RELOAD_INDICATOR_OFFSET: f32 = 0.6;
+/// In world units per second.
+const LASER_SPEED: f32 = 10.0;
+/// Lasers that have been weakened below this by bouncing fizzle out.
+const MIN_LASER_DAMAGE: f32 = 0.1;
+/// How long a hitscan beam stays on screen, in seconds.
+const BEAM_DURATION: f32 = 0.15;
+
+const WEAPON_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+pub struct WeaponPlugin;
+
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<OutOfAmmo>()
+            .add_event::<Reloaded>()
+            .add_event::<HitscanShot>()
+            .add_event::<Hit>()
+            .add_system(shoot.label(Shot).after(SwitchedWeapon).after(PlayerMoved))
+            .add_system(switch_weapon.label(SwitchedWeapon))
+            .add_system(regenerate_energy)
+            .add_system(reload_weapons.before(Shot).after(SwitchedWeapon))
+            .add_system(resolve_hitscan.after(Shot))
+            .add_system(move_laser.label(LasersMoved))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                on_laser_hit.label(HitsResolved).after(CollisionsDetected),
+            )
+            .add_system(weapon_hud)
+            .add_system(reload_indicator.after(CameraMoved));
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct SwitchedWeapon;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct Shot;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct LasersMoved;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, SystemLabel)]
+pub struct HitsResolved;
+
+#[derive(Component)]
+pub struct Laser {
+    lifetime: Duration,
+    origin: Duration,
+    damage: f32,
+    ricochet: Option<Ricochet>,
+    bounces: u32,
+}
+
+impl Laser {
+    pub fn new(damage: f32, ricochet: Option<Ricochet>, now: Duration) -> Self {
+        Laser {
+            lifetime: Duration::from_secs(1),
+            origin: now,
+            damage,
+            ricochet,
+            bounces: 0,
+        }
+    }
+}
+
+/// Something got hit by a laser or hitscan shot.
+pub struct Hit {
+    pub target: Entity,
+    pub damage: f32,
+}
+
+#[derive(Inspectable, Clone, Copy, Debug, PartialEq)]
+pub struct Ricochet {
+    pub max_bounces: u32,
+    /// Fraction of damage kept after each bounce.
+    pub falloff: f32,
+}
+
+impl Default for Ricochet {
+    fn default() -> Self {
+        Ricochet {
+            max_bounces: 3,
+            falloff: 0.6,
+        }
+    }
+}
+
+#[derive(Inspectable, Clone, Copy, Debug, Default, PartialEq)]
+pub enum FireMode {
+    #[default]
+    Laser,
+    Hitscan {
+        range: f32,
+    },
+}
+
+#[derive(Inspectable, Clone, Copy, Debug, PartialEq)]
+pub enum Ammo {
+    Energy {
+        cost: f32,
+    },
+    /// Refilled `reload_time` seconds after running dry, or after reloading by hand.
+    Rounds {
+        count: u32,
+        capacity: u32,
+        reload_time: f32,
+    },
+}
+
+impl Default for Ammo {
+    fn default() -> Self {
+        Ammo::Energy { cost: 10.0 }
+    }
+}
+
+#[derive(Inspectable, Clone, Debug)]
+pub struct Weapon {
+    pub name: String,
+    pub fire: FireMode,
+    pub ammo: Ammo,
+    pub damage: f32,
+    pub ricochet: Option<Ricochet>,
+    pub projectiles: u32,
+    pub spread: f32,
+    /// Seconds left until the magazine is full again, while reloading.
+    pub reloading: Option<f32>,
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        Weapon {
+            name: "Blaster".to_string(),
+            fire: FireMode::default(),
+            ammo: Ammo::default(),
+            damage: 1.0,
+            ricochet: None,
+            projectiles: 1,
+            spread: 0.0,
+            reloading: None,
+        }
+    }
+}
+
+impl Weapon {
+    pub fn scatter() -> Self {
+        Weapon {
+            name: "Scatter".to_string(),
+            fire: FireMode::Laser,
+            ammo: Ammo::Rounds {
+                count: 24,
+                capacity: 24,
+                reload_time: 1.5,
+            },
+            damage: 0.5,
+            ricochet: None,
+            projectiles: 5,
+            spread: 0.4,
+            reloading: None,
+        }
+    }
+
+    pub fn bouncer() -> Self {
+        Weapon {
+            name: "Bouncer".to_string(),
+            fire: FireMode::Laser,
+            ammo: Ammo::Energy { cost: 15.0 },
+            damage: 1.0,
+            ricochet: Some(Ricochet::default()),
+            projectiles: 1,
+            spread: 0.0,
+            reloading: None,
+        }
+    }
+
+    pub fn railgun() -> Self {
+        Weapon {
+            name: "Railgun".to_string(),
+            fire: FireMode::Hitscan { range: 12.0 },
+            ammo: Ammo::Energy { cost: 25.0 },
+            damage: 3.0,
+            ricochet: None,
+            projectiles: 1,
+            spread: 0.0,
+            reloading: None,
+        }
+    }
+
+    /// Takes one shot's worth of ammo, returning false if there isn't enough.
+    fn consume(&mut self, energy: &mut Energy) -> bool {
+        match &mut self.ammo {
+            Ammo::Energy { cost } if energy.current >= *cost => {
+                energy.current -= *cost;
+                true
+            }
+            Ammo::Rounds { count, .. } if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Starts reloading, unless the weapon doesn't take rounds, is already reloading
+    /// or has a full magazine. Returns whether it started.
+    fn start_reload(&mut self) -> bool {
+        match self.ammo {
+            Ammo::Rounds {
+                count,
+                capacity,
+                reload_time,
+            } if count < capacity && self.reloading.is_none() => {
+                self.reloading = Some(reload_time);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Advances a reload by `dt` seconds, returning true when it finishes.
+    fn tick_reload(&mut self, dt: f32) -> bool {
+        let left = match self.reloading.as_mut() {
+            Some(left) => left,
+            None => return false,
+        };
+        *left -= dt;
+        if *left > 0.0 {
+            return false;
+        }
+        self.reloading = None;
+        if let Ammo::Rounds {
+            count, capacity, ..
+        } = &mut self.ammo
+        {
+            *count = *capacity;
+        }
+        true
+    }
+
+    fn ammo_text(&self, energy: &Energy) -> String {
+        match self.ammo {
+            Ammo::Energy { cost } => format!("{} shots", (energy.current / cost) as u32),
+            Ammo::Rounds { .. } if self.reloading.is_some() => "reloading".to_string(),
+            Ammo::Rounds {
+                count, capacity, ..
+            } => format!("{}/{}", count, capacity),
+        }
+    }
+}
+
+#[derive(Component, Inspectable)]
+pub struct Arsenal {
+    pub weapons: Vec<Weapon>,
+    pub current: usize,
+}
+
+impl Default for Arsenal {
+    fn default() -> Self {
+        Arsenal {
+            weapons: vec![
+                Weapon::default(),
+                Weapon::scatter(),
+                Weapon::bouncer(),
+                Weapon::railgun(),
+            ],
+            current: 0,
+        }
+    }
+}
+
+impl Arsenal {
+    pub fn current_mut(&mut self) -> Option<&mut Weapon> {
+        self.weapons.get_mut(self.current)
+    }
+
+    fn select(&mut self, index: usize) {
+        if index < self.weapons.len() {
+            self.current = index;
+        }
+    }
+
+    fn cycle(&mut self, step: i32) {
+        let len = self.weapons.len() as i32;
+        if len > 0 {
+            self.current = (self.current as i32 + step).rem_euclid(len) as usize;
+        }
+    }
+}
+
+#[derive(Component, Inspectable)]
+pub struct Energy {
+    pub current: f32,
+    pub max: f32,
+    pub regen: f32,
+}
+
+impl Default for Energy {
+    fn default() -> Self {
+        Energy {
+            current: 100.0,
+            max: 100.0,
+            regen: 15.0,
+        }
+    }
+}
+
+pub struct OutOfAmmo {
+    pub shooter: Entity,
+    pub weapon: String,
+}
+
+/// A weapon's magazine was refilled.
+pub struct Reloaded {
+    pub shooter: Entity,
+    pub weapon: String,
+}
+
+pub struct HitscanShot {
+    pub origin: Vec2,
+    pub direction: Vec2,
+    pub range: f32,
+    pub damage: f32,
+}
+
+/// The direction a sprite is pointing in, given its rotation.
+pub fn facing(rotation: Quat) -> Vec2 {
+    (rotation * Quat::from_rotation_z(PI / 2.0))
+        .mul_vec3(Vec3::new(1., 0., 0.))
+        .xy()
+}
+
+fn switch_weapon(
+    mut arsenals: Query<(&Controls, &mut Arsenal), With<Player>>,
+    keyboard: Res<Input<KeyCode>>,
+    mut wheel: EventReader<MouseWheel>,
+    buttons: Res<Input<GamepadButton>>,
+) {
+    let mut scroll: f32 = wheel.iter().map(|ev| ev.y).sum();
+    // Ctrl + wheel zooms the camera instead.
+    if keyboard.any_pressed([KeyCode::LControl, KeyCode::RControl]) {
+        scroll = 0.0;
+    }
+    for (controls, mut arsenal) in arsenals.iter_mut() {
+        match *controls {
+            Controls::KeyboardMouse => {
+                if let Some(index) = WEAPON_KEYS.iter().position(|&k| keyboard.just_pressed(k)) {
+                    arsenal.select(index);
+                }
+                if scroll > 0.0 {
+                    arsenal.cycle(-1);
+                } else if scroll < 0.0 {
+                    arsenal.cycle(1);
+                }
+            }
+            Controls::Gamepad(gamepad) => {
+                if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::LeftTrigger)) {
+                    arsenal.cycle(-1);
+                }
+                if buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::RightTrigger)) {
+                    arsenal.cycle(1);
+                }
+            }
+        }
+    }
+}
+
+fn regenerate_energy(mut energies: Query<&mut Energy>, time: Res<Time>) {
+    for mut energy in energies.iter_mut() {
+        if energy.current < energy.max {
+            energy.current = (energy.current + energy.regen * time.delta_seconds()).min(energy.max);
+        }
+    }
+}
+
+/// R, or X on a gamepad, reloads the current weapon. Empty magazines reload by
+/// themselves, and every weapon keeps reloading while another one is out.
+fn reload_weapons(
+    mut arsenals: Query<(Entity, &Controls, &mut Arsenal), With<Player>>,
+    keyboard: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut reloaded: EventWriter<Reloaded>,
+    time: Res<Time>,
+) {
+    for (shooter, controls, mut arsenal) in arsenals.iter_mut() {
+        let pressed = match *controls {
+            Controls::KeyboardMouse => keyboard.just_pressed(KeyCode::R),
+            Controls::Gamepad(gamepad) => {
+                buttons.just_pressed(GamepadButton(gamepad, GamepadButtonType::West))
+            }
+        };
+        if pressed {
+            if let Some(weapon) = arsenal.current_mut() {
+                weapon.start_reload();
+            }
+        }
+
+        for weapon in arsenal.weapons.iter_mut() {
+            if matches!(weapon.ammo, Ammo::Rounds { count: 0, .. }) {
+                weapon.start_reload();
+            }
+            if weapon.tick_reload(time.delta_seconds()) {
+                reloaded.send(Reloaded {
+                    shooter,
+                    weapon: weapon.name.clone(),
+                });
+            }
+        }
+    }
+}
+
+fn shoot(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut query: Query<(Entity, &Transform, &PlayerInput, &mut Arsenal, &mut Energy), With<Player>>,
+    mut out_of_ammo: EventWriter<OutOfAmmo>,
+    mut hitscan: EventWriter<HitscanShot>,
+    time: Res<Time>,
+) {
+    for (shooter, transform, input, mut arsenal, mut energy) in query.iter_mut() {
+        if !input.fire {
+            continue;
+        }
+        let weapon = match arsenal.current_mut() {
+            Some(weapon) => weapon,
+            None => continue,
+        };
+        if weapon.reloading.is_some() {
+            continue;
+        }
+
+        if !weapon.consume(&mut energy) {
+            out_of_ammo.send(OutOfAmmo {
+                shooter,
+                weapon: weapon.name.clone(),
+            });
+            continue;
+        }
+        // The magazine reloads by itself from here, but let the HUD know it ran dry.
+        if matches!(weapon.ammo, Ammo::Rounds { count: 0, .. }) {
+            out_of_ammo.send(OutOfAmmo {
+                shooter,
+                weapon: weapon.name.clone(),
+            });
+        }
+
+        if let FireMode::Hitscan { range } = weapon.fire {
+            hitscan.send(HitscanShot {
+                origin: transform.translation.xy(),
+                direction: facing(transform.rotation),
+                range,
+                damage: weapon.damage,
+            });
+            continue;
+        }
+
+        let laser_image = assets.load(LASER_SPRITE);
+
+        for i in 0..weapon.projectiles {
+            let offset = if weapon.projectiles > 1 {
+                weapon.spread * (i as f32 / (weapon.projectiles - 1) as f32 - 0.5)
+            } else {
+                0.0
+            };
+
+            spawn_laser(
+                &mut commands,
+                laser_image.clone(),
+                transform.translation.xy(),
+                transform.rotation * Quat::from_rotation_z(offset),
+                Laser::new(weapon.damage, weapon.ricochet, time.time_since_startup()),
+                Layers::TERRAIN | Layers::ENEMY,
+            );
+        }
+    }
+}
+
+pub fn spawn_laser(
+    commands: &mut Commands,
+    texture: Handle<Image>,
+    position: Vec2,
+    rotation: Quat,
+    laser: Laser,
+    targets: Layers,
+) -> Entity {
+    commands
+        .spawn_bundle(SpriteBundle {
+            transform: Transform {
+                translation: position.extend(0.),
+                rotation,
+                scale: Vec3::splat(1.0 / 64.0),
+            },
+            texture,
+            ..Default::default()
+        })
+        .insert(laser)
+        .insert(Collider {
+            radius: 0.0,
+            layers: Layers::PROJECTILE,
+            mask: targets,
+        })
+        .insert(Swept::default())
+        .insert(Name::new("Laser"))
+        .insert(DebugCircle {
+            color: Color::BLUE,
+            radius: 0.2,
+        })
+        .id()
+}
+
+/// Rotation that makes a sprite face along `direction`, the inverse of [`facing`].
+pub fn rotation_towards(direction: Vec2) -> Quat {
+    Quat::from_rotation_z(direction.y.atan2(direction.x) - PI / 2.0)
+}
+
+fn move_laser(
+    mut commands: Commands,
+    mut lasers: Query<(Entity, &mut Laser, &mut Swept, &mut Transform)>,
+    tilemap: Res<TileMap>,
+    time: Res<Time>,
+) {
+    let speed = LASER_SPEED * time.delta_seconds();
+    for (entity, mut laser, mut swept, mut transform) in lasers.iter_mut() {
+        if laser.lifetime + laser.origin <= time.time_since_startup() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+
+        let start = transform.translation.xy();
+        let direction = facing(transform.rotation);
+        match sweep_laser(
+            &mut laser,
+            start,
+            direction,
+            speed,
+            &tilemap,
+            &mut swept.path,
+        ) {
+            Some((position, direction)) => {
+                transform.translation = position.extend(transform.translation.z);
+                transform.rotation = rotation_towards(direction);
+            }
+            None => commands.entity(entity).despawn(),
+        }
+    }
+}
+
+/// Moves a laser `distance` along `direction`, bouncing off walls while its ricochet
+/// allows. Replaces `path` with the corners of the route taken, and returns where the
+/// laser ends up and where it's heading, or `None` if it was stopped or fizzled out.
+fn sweep_laser(
+    laser: &mut Laser,
+    mut position: Vec2,
+    mut direction: Vec2,
+    distance: f32,
+    tilemap: &TileMap,
+    path: &mut Vec<Vec2>,
+) -> Option<(Vec2, Vec2)> {
+    let mut remaining = distance;
+    path.clear();
+    path.push(position);
+    while let Some(hit) = ray_grid(position, direction, remaining, |tile| {
+        tilemap.is_solid(tile)
+    }) {
+        let can_bounce = laser
+            .ricochet
+            .is_some_and(|ricochet| laser.bounces < ricochet.max_bounces);
+        // A laser that starts inside a wall has no face to bounce off.
+        if !can_bounce || hit.distance <= 0.0 {
+            return None;
+        }
+        let falloff = laser.ricochet.map_or(1.0, |ricochet| ricochet.falloff);
+        laser.bounces += 1;
+        laser.damage *= falloff;
+
+        // Nudge off the face so the next cast doesn't start inside the wall.
+        position += hit.distance * direction + 1e-4 * hit.normal;
+        direction = reflect(direction, hit.normal);
+        remaining -= hit.distance;
+        path.push(position);
+    }
+
+    if laser.damage < MIN_LASER_DAMAGE {
+        return None;
+    }
+
+    position += remaining * direction;
+    path.push(position);
+    Some((position, direction))
+}
+
+fn resolve_hitscan(
+    mut draw: DebugDraw,
+    mut shots: EventReader<HitscanShot>,
+    mut hits: EventWriter<Hit>,
+    grid: Res<SpatialGrid>,
+    tilemap: Res<TileMap>,
+) {
+    for shot in shots.iter() {
+        let wall = ray_grid(shot.origin, shot.direction, shot.range, |tile| {
+            tilemap.is_solid(tile)
+        });
+        let mut length = wall.map_or(shot.range, |hit| hit.distance);
+
+        let hit = grid.cast_ray(
+            shot.origin,
+            shot.direction,
+            length,
+            Layers::TERRAIN | Layers::ENEMY,
+        );
+        if let Some((target, distance)) = hit {
+            hits.send(Hit {
+                target,
+                damage: shot.damage,
+            });
+            length = distance;
+        }
+
+        draw.timed(BEAM_DURATION).line(
+            shot.origin,
+            shot.origin + length * shot.direction,
+            Color::ORANGE_RED,
+        );
+    }
+}
+
+fn on_laser_hit(
+    mut commands: Commands,
+    mut collisions: EventReader<CollisionEvent>,
+    mut hits: EventWriter<Hit>,
+    lasers: Query<&Laser>,
+) {
+    for collision in collisions.iter() {
+        if let Ok(laser) = lasers.get(collision.entity) {
+            commands.entity(collision.entity).despawn_recursive();
+            hits.send(Hit {
+                target: collision.other,
+                damage: laser.damage,
+            });
+        }
+    }
+}
+
+/// Each player's weapons and energy, in the corner of their view.
+fn weapon_hud(
+    mut egui_context: ResMut<EguiContext>,
+    arsenals: Query<(&Player, &Arsenal, &Energy)>,
+    views: Query<&PlayerView>,
+    mut out_of_ammo: EventReader<OutOfAmmo>,
+    mut reloaded: EventReader<Reloaded>,
+    mut last_empty: Local<HashMap<Entity, (Duration, String)>>,
+    time: Res<Time>,
+) {
+    let now = time.time_since_startup();
+    for ev in out_of_ammo.iter() {
+        last_empty.insert(ev.shooter, (now, ev.weapon.clone()));
+    }
+    for ev in reloaded.iter() {
+        if last_empty
+            .get(&ev.shooter)
+            .is_some_and(|(_, weapon)| *weapon == ev.weapon)
+        {
+            last_empty.remove(&ev.shooter);
+        }
+    }
+    last_empty.retain(|_, (since, _)| now - *since < Duration::from_millis(500));
+
+    for view in views.iter() {
+        let (player, arsenal, energy) = match arsenals.get(view.player) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        // egui measures from the top left, the viewport from the bottom left.
+        let corner = view.viewport.min + Vec2::splat(10.0);
+        egui::Window::new(format!("Player {}", player.index + 1))
+            .id(egui::Id::new(("weapon_hud", player.index)))
+            .anchor(egui::Align2::LEFT_BOTTOM, [corner.x, -corner.y])
+            .resizable(false)
+            .collapsible(false)
+            .show(egui_context.ctx_mut(), |ui| {
+                for (i, weapon) in arsenal.weapons.iter().enumerate() {
+                    let text = format!("{} {}: {}", i + 1, weapon.name, weapon.ammo_text(energy));
+                    if i == arsenal.current {
+                        ui.colored_label(egui::Color32::WHITE, text);
+                    } else {
+                        ui.label(text);
+                    }
+                }
+                ui.add(
+                    egui::ProgressBar::new(energy.current / energy.max)
+                        .text(format!("Energy {:.0}/{:.0}", energy.current, energy.max)),
+                );
+                if let Some((_, weapon)) = last_empty.get(&view.player) {
+                    ui.colored_label(egui::Color32::RED, format!("{} is empty!", weapon));
+                }
+            });
+    }
+}
+
+/// Floats a note over players whose weapon is reloading, in their own view.
+fn reload_indicator(
+    mut egui_context: ResMut<EguiContext>,
+    players: Query<(&Transform, &Arsenal), With<Player>>,
+    views: Query<(&PlayerView, &CameraRig, &OrthographicProjection)>,
+    windows: Res<Windows>,
+) {
+    let window_height = windows.get_primary().unwrap().height();
+    for (view, rig, projection) in views.iter() {
+        let (transform, arsenal) = match players.get(view.player) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let reloading = arsenal
+            .weapons
+            .get(arsenal.current)
+            .is_some_and(|weapon| weapon.reloading.is_some());
+        if !reloading {
+            continue;
+        }
+        // Against the rig rather than the shaking camera, so the note stays readable.
+        let camera = GlobalTransform::from_translation(rig.position().extend(0.0));
+        let above = transform.translation.xy() + RELOAD_INDICATOR_OFFSET * Vec2::Y;
+        let pixel =
+            view.viewport.min + world_to_screen(above, view.viewport.size, projection, &camera);
+        // egui measures from the top left, the viewport from the bottom left.
+        egui::Area::new(("reload_indicator", view.index))
+            .fixed_pos([pixel.x, window_height - pixel.y])
+            .interactable(false)
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.colored_label(egui::Color32::YELLOW, "Reloading");
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::CollisionPlugin;
+    use bevy::ecs::event::{Events, ManualEventReader};
+
+    #[test]
+    fn slow_frame_laser_hits_rock_it_passes() {
+        let mut app = App::new();
+        app.add_plugin(CollisionPlugin)
+            .add_event::<Hit>()
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                on_laser_hit.after(CollisionsDetected),
+            );
+
+        // A single 5 FPS frame covers 2 units, so the laser starts short of the rock
+        // and ends well past it.
+        let mut laser = Laser::new(1.0, None, Duration::ZERO);
+        let mut path = Vec::new();
+        let (end, _) = sweep_laser(
+            &mut laser,
+            Vec2::ZERO,
+            Vec2::X,
+            LASER_SPEED * 0.2,
+            &TileMap::default(),
+            &mut path,
+        )
+        .unwrap();
+        assert_eq!(path, vec![Vec2::ZERO, Vec2::new(2.0, 0.0)]);
+
+        let rock = app
+            .world
+            .spawn()
+            .insert(Transform::from_xyz(1.0, 0.0, 0.0))
+            .insert(Collider {
+                radius: 0.3,
+                layers: Layers::TERRAIN,
+                mask: Layers::empty(),
+            })
+            .id();
+        app.world
+            .spawn()
+            .insert(Transform::from_translation(end.extend(0.0)))
+            .insert(laser)
+            .insert(Collider {
+                radius: 0.0,
+                layers: Layers::PROJECTILE,
+                mask: Layers::TERRAIN | Layers::ENEMY,
+            })
+            .insert(Swept { path });
+
+        app.update();
+
+        let events = app.world.resource::<Events<Hit>>();
+        let hits: Vec<Entity> = ManualEventReader::<Hit>::default()
+            .iter(events)
+            .map(|hit| hit.target)
+            .collect();
+        assert_eq!(hits, vec![rock]);
+    }
+
+    /// Takes every round out of the current weapon's magazine.
+    fn empty(weapon: &mut Weapon) {
+        let mut energy = Energy::default();
+        while weapon.consume(&mut energy) {}
+    }
+
+    #[test]
+    fn empty_magazine_refills_after_reload_time() {
+        let mut weapon = Weapon::scatter();
+        empty(&mut weapon);
+        assert!(weapon.start_reload());
+        assert!(!weapon.tick_reload(1.0));
+        assert_eq!(weapon.ammo_text(&Energy::default()), "reloading");
+        assert!(weapon.tick_reload(0.6));
+        assert_eq!(weapon.reloading, None);
+        assert!(matches!(weapon.ammo, Ammo::Rounds { count: 24, .. }));
+    }
+
+    #[test]
+    fn reload_only_starts_when_it_would_help() {
+        // Full magazine, and no magazine at all.
+        assert!(!Weapon::scatter().start_reload());
+        assert!(!Weapon::default().start_reload());
+
+        let mut weapon = Weapon::scatter();
+        let mut energy = Energy::default();
+        assert!(weapon.consume(&mut energy));
+        assert!(weapon.start_reload());
+        // Already under way.
+        assert!(!weapon.start_reload());
+    }
+
+    #[test]
+    fn empty_weapons_reload_by_themselves() {
+        let mut app = App::new();
+        app.add_event::<Reloaded>()
+            .init_resource::<Time>()
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<Input<GamepadButton>>()
+            .add_system(reload_weapons);
+
+        let mut scatter = Weapon::scatter();
+        empty(&mut scatter);
+        // Instant, since time doesn't pass in this app.
+        if let Ammo::Rounds { reload_time, .. } = &mut scatter.ammo {
+            *reload_time = 0.0;
+        }
+        let player = app
+            .world
+            .spawn()
+            .insert(Player::new(0))
+            .insert(Controls::KeyboardMouse)
+            .insert(Arsenal {
+                weapons: vec![Weapon::default(), scatter],
+                // Not even the one in hand.
+                current: 0,
+            })
+            .id();
+
+        app.update();
+
+        let arsenal = app.world.get::<Arsenal>(player).unwrap();
+        assert!(matches!(
+            arsenal.weapons[1].ammo,
+            Ammo::Rounds { count: 24, .. }
+        ));
+        let events = app.world.resource::<Events<Reloaded>>();
+        let reloaded: Vec<_> = ManualEventReader::<Reloaded>::default()
+            .iter(events)
+            .map(|ev| (ev.shooter, ev.weapon.clone()))
+            .collect();
+        assert_eq!(reloaded, vec![(player, "Scatter".to_string())]);
+    }
+}